@@ -0,0 +1,117 @@
+#![deny(missing_docs, warnings)]
+
+//! Derive macros for the emitter crate.
+//!
+//! These are re-exported by emitter when its `derive` feature is enabled.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Index, Member, Type};
+
+/// Derive `Event` for a type.
+///
+/// By default the type is its own data, so `#[derive(Event)] struct Resize`
+/// implements `Event<Resize> for Resize`. A marker type can name its data
+/// with `#[event(payload = Type)]` instead.
+#[proc_macro_derive(Event, attributes(event))]
+pub fn derive_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand_event(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into()
+    }
+}
+
+fn expand_event(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let mut payload: Option<Type> = None;
+
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("event")) {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("payload") {
+                return Err(meta.error("expected `payload = Type`"));
+            }
+
+            if payload.is_some() {
+                return Err(meta.error("an event can only have one payload"));
+            }
+
+            payload = Some(meta.value()?.parse()?);
+            Ok(())
+        })?;
+    }
+
+    // Events must be 'static, so their type parameters must be too.
+    let params: Vec<_> = input.generics.type_params().map(|param| param.ident.clone()).collect();
+    let where_clause = input.generics.make_where_clause();
+    for param in params {
+        where_clause.predicates.push(parse_quote!(#param: 'static));
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let payload = match payload {
+        Some(payload) => payload,
+        None => parse_quote!(#name #ty_generics)
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::emitter::Event<#payload> for #name #ty_generics #where_clause {}
+    })
+}
+
+/// Derive `Eventable` for a struct containing an `EventEmitter`.
+///
+/// The field holding the emitter is marked with `#[events]`.
+#[proc_macro_derive(Eventable, attributes(events))]
+pub fn derive_eventable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand_eventable(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into()
+    }
+}
+
+fn expand_eventable(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match input.data {
+        Data::Struct(ref data) => &data.fields,
+        _ => return Err(syn::Error::new_spanned(&input.ident, "Eventable can only be derived for structs"))
+    };
+
+    let mut member: Option<Member> = None;
+
+    for (index, field) in fields.iter().enumerate() {
+        let attr = match field.attrs.iter().find(|attr| attr.path().is_ident("events")) {
+            Some(attr) => attr,
+            None => continue
+        };
+
+        attr.meta.require_path_only()?;
+
+        if member.is_some() {
+            return Err(syn::Error::new_spanned(attr, "only one field can be marked #[events]"));
+        }
+
+        member = Some(match field.ident {
+            Some(ref ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(index))
+        });
+    }
+
+    let member = match member {
+        Some(member) => member,
+        None => return Err(syn::Error::new_spanned(&input.ident, "expected a field marked #[events]"))
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::emitter::Eventable for #name #ty_generics #where_clause {
+            fn events(&self) -> &::emitter::EventEmitter { &self.#member }
+            fn events_mut(&mut self) -> &mut ::emitter::EventEmitter { &mut self.#member }
+        }
+    })
+}
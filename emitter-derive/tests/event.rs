@@ -0,0 +1,68 @@
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use emitter::{Event, EventEmitter, Eventable};
+
+#[derive(Event)]
+struct Resize {
+    width: u32,
+    height: u32,
+}
+
+#[derive(Event)]
+#[event(payload = (i32, i32))]
+struct Scroll;
+
+#[derive(Event)]
+#[event(payload = str)]
+struct Log;
+
+#[derive(Event)]
+struct Loaded<T>(T);
+
+#[derive(Event)]
+#[event(payload = Vec<T>)]
+struct Batch<T>(PhantomData<T>);
+
+#[test]
+fn test_payload_is_the_event() {
+    let emitter = EventEmitter::new();
+    let seen = Rc::new(RefCell::new(vec![]));
+
+    let inner = seen.clone();
+    emitter.on::<Resize, _, Resize>(move |size| inner.borrow_mut().push(size.width * size.height));
+
+    emitter.trigger::<Resize, Resize>(&Resize { width: 3, height: 4 });
+    assert_eq!(*seen.borrow(), vec![12]);
+}
+
+#[test]
+fn test_payload_attribute() {
+    let emitter = EventEmitter::new();
+    let seen = Rc::new(RefCell::new(vec![]));
+
+    let inner = seen.clone();
+    emitter.on::<Scroll, _, (i32, i32)>(move |delta| inner.borrow_mut().push(format!("{:?}", delta)));
+    let inner = seen.clone();
+    emitter.on::<Log, _, str>(move |line| inner.borrow_mut().push(line.to_string()));
+
+    emitter.trigger::<Scroll, (i32, i32)>(&(1, -1));
+    emitter.trigger::<Log, str>("done");
+    assert_eq!(*seen.borrow(), vec!["(1, -1)", "done"]);
+}
+
+#[test]
+fn test_generic_events() {
+    let emitter = EventEmitter::new();
+    let seen = Rc::new(RefCell::new(vec![]));
+
+    let inner = seen.clone();
+    emitter.on::<Loaded<u8>, _, Loaded<u8>>(move |loaded| inner.borrow_mut().push(loaded.0 as usize));
+    let inner = seen.clone();
+    emitter.on::<Batch<u8>, _, Vec<u8>>(move |batch| inner.borrow_mut().push(batch.len()));
+
+    emitter.trigger::<Loaded<u8>, Loaded<u8>>(&Loaded(5));
+    emitter.trigger::<Batch<u8>, Vec<u8>>(&vec![1, 2, 3]);
+    assert_eq!(*seen.borrow(), vec![5, 3]);
+}
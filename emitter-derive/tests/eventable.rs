@@ -0,0 +1,42 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emitter::{Event, EventEmitter, Eventable};
+
+struct Clicked;
+impl Event<u32> for Clicked {}
+
+#[derive(Eventable)]
+struct Button {
+    label: String,
+    #[events]
+    emitter: EventEmitter,
+}
+
+#[derive(Eventable)]
+struct Wrapper<T>(T, #[events] EventEmitter);
+
+#[test]
+fn test_named_field() {
+    let mut button = Button { label: "ok".to_string(), emitter: EventEmitter::new() };
+    let seen = Rc::new(RefCell::new(vec![]));
+
+    let inner = seen.clone();
+    button.on::<Clicked, _, u32>(move |x| inner.borrow_mut().push(*x));
+    button.trigger::<Clicked, u32>(&1);
+
+    button.events_mut().on::<Clicked, _, u32>(|_| {});
+    assert_eq!(button.label, "ok");
+    assert_eq!(*seen.borrow(), vec![1]);
+}
+
+#[test]
+fn test_generic_tuple_struct() {
+    let wrapper = Wrapper(5u8, EventEmitter::new());
+    let seen = Rc::new(RefCell::new(vec![]));
+
+    let inner = seen.clone();
+    wrapper.on::<Clicked, _, u32>(move |x| inner.borrow_mut().push(*x));
+    wrapper.trigger::<Clicked, u32>(&u32::from(wrapper.0));
+    assert_eq!(*seen.borrow(), vec![5]);
+}
@@ -0,0 +1,279 @@
+//! An event emitter for asynchronous callbacks.
+
+use std::any::TypeId;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use crate::{insert_listener, remove_listeners, snapshot, Event, Listener, ListenerId, Registration, Table};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+/// How an `AsyncEventEmitter` runs the futures of the callbacks for an event.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Dispatch {
+    /// Call each callback only once the future of the previous one has completed.
+    Sequential,
+    /// Call every callback straight away and wait for all of their futures together.
+    Concurrent,
+}
+
+type AsyncCallback<X> = Rc<dyn Fn(&X) -> BoxFuture>;
+
+/// An event emitter whose callbacks return futures.
+///
+/// Triggering an event returns a future which completes once the futures of
+/// all callbacks have, run according to the emitter's `Dispatch`. It does not
+/// depend on any particular executor.
+///
+/// Like `EventEmitter`, a trigger dispatches to a snapshot of the callbacks
+/// registered when `trigger` was called.
+///
+/// Like `EventEmitter`, this is for use on a single thread: callbacks and
+/// their futures need not be `Send`, so neither is the future returned by
+/// `trigger`. It cannot be passed to `tokio::spawn` or any other executor
+/// which may move futures between threads; run it on the current thread,
+/// for example with `tokio::task::spawn_local` or by awaiting it directly.
+pub struct AsyncEventEmitter {
+    events: RefCell<Table>,
+    dispatch: Dispatch,
+    next_id: Cell<usize>,
+}
+
+impl AsyncEventEmitter {
+    /// Create an AsyncEventEmitter which runs callbacks sequentially.
+    pub fn new() -> AsyncEventEmitter {
+        AsyncEventEmitter::with_dispatch(Dispatch::Sequential)
+    }
+
+    /// Create an AsyncEventEmitter which runs callbacks as given by `dispatch`.
+    pub fn with_dispatch(dispatch: Dispatch) -> AsyncEventEmitter {
+        AsyncEventEmitter { events: RefCell::new(HashMap::new()), dispatch, next_id: Cell::new(0) }
+    }
+
+    fn register<E, X>(&self, once: bool, callback: AsyncCallback<X>) -> ListenerId
+    where E: Event<X>, X: ?Sized + 'static {
+        let id = ListenerId(self.next_id.get());
+        self.next_id.set(id.0 + 1);
+
+        let registration = Registration { once, ..Registration::default() };
+        insert_listener::<E, _>(&self.events, id, registration, callback);
+        id
+    }
+
+    fn remove<E: 'static>(&self, ids: &[ListenerId]) -> bool {
+        remove_listeners(&self.events, TypeId::of::<E>(), ids).is_some()
+    }
+
+    // Start the callback of a listener, unless it is a `once` listener which
+    // has already been started.
+    fn start<E, X>(&self, listener: &Listener<AsyncCallback<X>>, event: &X) -> Option<BoxFuture>
+    where E: Event<X>, X: ?Sized + 'static {
+        let mut spent = vec![];
+        if !listener.claim(&mut spent) { return None }
+        if !spent.is_empty() { self.remove::<E>(&spent); }
+
+        Some((listener.callback)(event))
+    }
+}
+
+impl Default for AsyncEventEmitter {
+    fn default() -> AsyncEventEmitter { AsyncEventEmitter::new() }
+}
+
+// Adapt a callback to return a boxed future.
+fn boxed<F, X, Fut>(callback: F) -> AsyncCallback<X>
+where F: Fn(&X) -> Fut + 'static, X: ?Sized, Fut: Future<Output = ()> + 'static {
+    Rc::new(move |event: &X| Box::pin(callback(event)) as BoxFuture)
+}
+
+/// The asynchronous counterpart of `Eventable`.
+///
+/// A type is AsyncEventable if it contains an AsyncEventEmitter.
+pub trait AsyncEventable {
+    /// Get a reference to the enclosed emitter.
+    fn events(&self) -> &AsyncEventEmitter;
+
+    /// Register a callback to be fired when an event is triggered.
+    ///
+    /// The future returned by the callback cannot borrow the event data, so
+    /// anything it needs must be copied out before it is created.
+    fn on<E, F, X, Fut>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) -> Fut + 'static, X: ?Sized + 'static,
+          Fut: Future<Output = ()> + 'static {
+        self.events().register::<E, X>(false, boxed(callback))
+    }
+
+    /// Register a callback to be fired only the next time an event is triggered.
+    fn once<E, F, X, Fut>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) -> Fut + 'static, X: ?Sized + 'static,
+          Fut: Future<Output = ()> + 'static {
+        self.events().register::<E, X>(true, boxed(callback))
+    }
+
+    /// Remove a callback previously registered for this event with `on`.
+    ///
+    /// Returns false if no such callback was registered.
+    fn off<E, X>(&self, id: ListenerId) -> bool
+    where E: Event<X>, X: ?Sized + 'static {
+        self.events().remove::<E>(&[id])
+    }
+
+    /// Trigger an event, returning a future which runs all of the associated
+    /// handlers.
+    fn trigger<'a, E, X>(&'a self, event: &'a X) -> impl Future<Output = ()> + 'a
+    where E: Event<X>, X: ?Sized + 'static {
+        let emitter = self.events();
+
+        let list = snapshot::<E, AsyncCallback<X>>(&emitter.events).unwrap_or_default();
+
+        async move {
+            match emitter.dispatch {
+                Dispatch::Sequential => {
+                    for listener in list.iter() {
+                        if let Some(future) = emitter.start::<E, X>(listener, event) {
+                            future.await
+                        }
+                    }
+                },
+                Dispatch::Concurrent => {
+                    let futures = list.iter()
+                        .filter_map(|listener| emitter.start::<E, X>(listener, event))
+                        .map(Some)
+                        .collect();
+
+                    JoinAll { futures }.await
+                }
+            }
+        }
+    }
+}
+
+// AsyncEventEmitter is itself eventable, so can be used directly.
+impl AsyncEventable for AsyncEventEmitter {
+    fn events(&self) -> &AsyncEventEmitter { self }
+}
+
+// Polls a set of futures until all of them have completed.
+struct JoinAll {
+    futures: Vec<Option<BoxFuture>>,
+}
+
+impl Future for JoinAll {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut done = true;
+
+        for slot in self.get_mut().futures.iter_mut() {
+            if let Some(future) = slot {
+                if future.as_mut().poll(cx).is_ready() {
+                    *slot = None;
+                } else {
+                    done = false;
+                }
+            }
+        }
+
+        if done { Poll::Ready(()) } else { Poll::Pending }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::future::Future;
+    use std::pin::{pin, Pin};
+    use std::rc::Rc;
+    use std::task::{Context, Poll, Waker};
+
+    use super::{AsyncEventEmitter, AsyncEventable, Dispatch};
+    use crate::Event;
+
+    struct Request;
+    impl Event<u32> for Request {}
+
+    // A minimal executor, which polls the future until it completes.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) { return output }
+        }
+    }
+
+    // A future which is pending the first time it is polled.
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 { return Poll::Ready(()) }
+
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    fn register_steps(emitter: &AsyncEventEmitter, seen: &Rc<RefCell<Vec<String>>>) {
+        for name in ["a", "b"] {
+            let seen = seen.clone();
+            emitter.on::<Request, _, u32, _>(move |x| {
+                let (seen, x) = (seen.clone(), *x);
+                async move {
+                    seen.borrow_mut().push(format!("{}{}", name, x));
+                    YieldNow(false).await;
+                    seen.borrow_mut().push(format!("{}{}", name, x + 1));
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn test_sequential_dispatch() {
+        let emitter = AsyncEventEmitter::new();
+        let seen = Rc::new(RefCell::new(vec![]));
+        register_steps(&emitter, &seen);
+
+        block_on(emitter.trigger::<Request, u32>(&1));
+        assert_eq!(*seen.borrow(), vec!["a1", "a2", "b1", "b2"]);
+    }
+
+    #[test]
+    fn test_concurrent_dispatch() {
+        let emitter = AsyncEventEmitter::with_dispatch(Dispatch::Concurrent);
+        let seen = Rc::new(RefCell::new(vec![]));
+        register_steps(&emitter, &seen);
+
+        block_on(emitter.trigger::<Request, u32>(&1));
+        assert_eq!(*seen.borrow(), vec!["a1", "b1", "a2", "b2"]);
+    }
+
+    #[test]
+    fn test_once_and_off() {
+        let emitter = AsyncEventEmitter::new();
+        let seen = Rc::new(RefCell::new(vec![]));
+
+        let inner = seen.clone();
+        emitter.once::<Request, _, u32, _>(move |x| {
+            inner.borrow_mut().push(*x);
+            async {}
+        });
+        let inner = seen.clone();
+        let id = emitter.on::<Request, _, u32, _>(move |x| {
+            inner.borrow_mut().push(*x * 10);
+            async {}
+        });
+
+        block_on(emitter.trigger::<Request, u32>(&1));
+        assert!(emitter.off::<Request, u32>(id));
+        block_on(emitter.trigger::<Request, u32>(&2));
+        assert_eq!(*seen.borrow(), vec![1, 10]);
+    }
+}
@@ -1,20 +1,669 @@
-#![feature(unboxed_closures)]
 #![deny(missing_docs, warnings)]
 
 //! A synchronous event emitter for evented code.
 
-use std::collections::HashMap;
+use std::any::{Any, TypeId};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
 use std::collections::hash_map::Entry;
-
-use std::intrinsics::TypeId;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
 use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::{Rc, Weak};
+
+#[cfg(feature = "derive")]
+pub use emitter_derive::{Event, Eventable};
+
+pub use crate::future::{AsyncEventEmitter, AsyncEventable, Dispatch};
+pub use crate::subscriber::Subscriber;
+pub use crate::sync::{SyncEventEmitter, SyncEventable};
+pub use crate::tree::{Bubbling, Node, Phase};
+
+pub mod future;
+pub mod subscriber;
+pub mod sync;
+pub mod tree;
 
 /// An event and the data associated with it.
-pub trait Event<Sized? X>: 'static {}
+///
+/// Each event type should be used with a single type of data; registering or
+/// triggering one event with two different data types panics.
+pub trait Event<X: ?Sized>: 'static {}
+
+/// Returned by a callback to decide whether an event continues to later callbacks.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Propagation {
+    /// Call the remaining callbacks for this event.
+    Continue,
+    /// Consume the event, skipping all remaining callbacks.
+    Stop,
+}
+
+/// A handle to a registered callback, used to remove it again with `off`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ListenerId(usize);
+
+/// A guard which removes a callback registered with `on_scoped` when dropped.
+#[must_use = "the callback is removed as soon as the Subscription is dropped"]
+pub struct Subscription {
+    table: Weak<RefCell<Table>>,
+    event: TypeId,
+    id: ListenerId,
+}
+
+impl Subscription {
+    /// The id of the subscribed callback.
+    pub fn id(&self) -> ListenerId { self.id }
+
+    /// Keep the callback registered, returning its id so it can still be
+    /// removed with `off`.
+    pub fn forget(mut self) -> ListenerId {
+        self.table = Weak::new();
+        self.id
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // The emitter may have been dropped already.
+        if let Some(table) = self.table.upgrade() {
+            let removed = remove_listeners(&table, self.event, &[self.id]);
+
+            // Dropped only now the table is released, as the callback may own
+            // further subscriptions.
+            drop(removed);
+        }
+    }
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription").field("id", &self.id).finish()
+    }
+}
+
+/// The errors returned by fallible callbacks during `try_trigger` or
+/// `try_trigger_all`, with the callbacks which returned them.
+#[derive(Debug)]
+pub struct TriggerError<Err> {
+    /// Each callback which failed, in the order they were called.
+    pub failures: Vec<(ListenerId, Err)>,
+    /// Each callback which panicked, when panics are isolated with
+    /// `EventEmitter::isolate_panics`.
+    pub panicked: Vec<ListenerId>,
+}
+
+impl<Err: fmt::Display> fmt::Display for TriggerError<Err> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (id, err)) in self.failures.iter().enumerate() {
+            if index != 0 { f.write_str("; ")?; }
+            write!(f, "listener {} failed: {}", id.0, err)?;
+        }
+
+        for (index, id) in self.panicked.iter().enumerate() {
+            if index != 0 || !self.failures.is_empty() { f.write_str("; ")?; }
+            write!(f, "listener {} panicked", id.0)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<Err: Error> Error for TriggerError<Err> {}
+
+/// A triggered event of any type, as seen by callbacks registered with `on_any`.
+pub struct AnyEvent<'a> {
+    event: TypeId,
+    name: &'static str,
+    // A `*const X` pointing at the event data, since the data may be unsized
+    // and so cannot be a `&dyn Any` itself. See `downcast_ref`.
+    data: &'a dyn Any,
+}
+
+impl<'a> AnyEvent<'a> {
+    fn new<E, X>(data: &'a *const X) -> AnyEvent<'a>
+    where E: Event<X>, X: ?Sized + 'static {
+        AnyEvent { event: TypeId::of::<E>(), name: std::any::type_name::<E>(), data }
+    }
+
+    /// The `TypeId` of the event type.
+    pub fn event_type(&self) -> TypeId { self.event }
+
+    /// The name of the event type, as given by `std::any::type_name`.
+    pub fn name(&self) -> &'static str { self.name }
+
+    /// Get the event data, if it is of type `X`.
+    pub fn downcast_ref<X: ?Sized + 'static>(&self) -> Option<&'a X> {
+        // SAFETY: AnyEvents are only created by `EventEmitter::notify_any`,
+        // from a pointer to the `&X` it was given, which is a local variable
+        // of that call and so borrowed for at most as long as that `&X`;
+        // hence the pointee outlives `'a`. The `TypeId` check made by
+        // `downcast_ref` ensures the pointer really is a `*const X`, and
+        // callbacks are given a `&AnyEvent<'_>` with a higher-ranked
+        // lifetime, so cannot keep the reference past their call.
+        self.data.downcast_ref::<*const X>().map(|&data| unsafe { &*data })
+    }
+}
+
+impl<'a> fmt::Debug for AnyEvent<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnyEvent").field("name", &self.name).finish()
+    }
+}
+
+// A registered callback, which either observes or modifies the event data.
+enum Callback<X: ?Sized> {
+    Ref(Rc<dyn Fn(&X) -> Propagation>),
+    Mut(Rc<dyn Fn(&mut X) -> Propagation>),
+}
+
+impl<X: ?Sized> Clone for Callback<X> {
+    fn clone(&self) -> Callback<X> {
+        match *self {
+            Callback::Ref(ref callback) => Callback::Ref(callback.clone()),
+            Callback::Mut(ref callback) => Callback::Mut(callback.clone())
+        }
+    }
+}
+
+// How a callback is registered, besides the callback itself. `SyncEventEmitter`
+// uses an `Arc`-based owner instead.
+struct Registration<O = Weak<dyn Any>> {
+    once: bool,
+    priority: i32,
+    // The listener is removed once its owner has been dropped.
+    owner: Option<O>,
+}
+
+impl<O> Default for Registration<O> {
+    fn default() -> Registration<O> { Registration { once: false, priority: 0, owner: None } }
+}
+
+// A single registered callback. Listeners are shared between the table and any
+// in-progress triggers, so `once` state lives behind its own `Rc`.
+#[derive(Clone)]
+struct Listener<C> {
+    id: ListenerId,
+    once: Option<Rc<Cell<bool>>>,
+    owner: Option<Weak<dyn Any>>,
+    priority: i32,
+    callback: C,
+}
+
+impl<C> Listener<C> {
+    // Whether the listener's owner, if it has one, is still alive.
+    fn alive(&self) -> bool {
+        self.owner.as_ref().is_none_or(|owner| owner.strong_count() != 0)
+    }
+
+    // Check whether the listener should be called, marking `once` listeners
+    // and listeners whose owner has been dropped as spent.
+    fn claim(&self, spent: &mut Vec<ListenerId>) -> bool {
+        if !self.alive() {
+            spent.push(self.id);
+            return false;
+        }
+
+        match self.once {
+            Some(ref fired) if fired.replace(true) => false,
+            Some(_) => { spent.push(self.id); true },
+            None => true
+        }
+    }
+}
+
+// The handlers registered for one event, with their callback type intact.
+// Registration replaces the list instead of mutating it, so a trigger can
+// dispatch from a snapshot.
+struct Handlers<C> {
+    name: &'static str,
+    list: Rc<Vec<Listener<C>>>,
+}
+
+// The type-erased interface to a `Handlers`, so the lookup table can hold the
+// handler lists of every event.
+trait HandlerList {
+    fn name(&self) -> &'static str;
+    // The number of listeners, not counting those whose owner has been
+    // dropped but which have not been pruned yet.
+    fn len(&self) -> usize;
+    // Remove listeners, returning the previous list if any were removed. The
+    // caller must release the table before dropping it, since dropping a
+    // callback may drop a `Subscription` which removes from the table too.
+    fn remove(&mut self, ids: &[ListenerId]) -> Option<Box<dyn Any>>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<C: Clone + 'static> HandlerList for Handlers<C> {
+    fn name(&self) -> &'static str { self.name }
+    fn len(&self) -> usize { self.list.iter().filter(|listener| listener.alive()).count() }
+
+    fn remove(&mut self, ids: &[ListenerId]) -> Option<Box<dyn Any>> {
+        if !self.list.iter().any(|listener| ids.contains(&listener.id)) { return None }
+
+        let list = self.list.iter().filter(|listener| !ids.contains(&listener.id)).cloned().collect();
+        Some(Box::new(mem::replace(&mut self.list, Rc::new(list))))
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+type Table = HashMap<TypeId, Box<dyn HandlerList>>;
+
+// Add a listener to the handlers for an event, which must all have callbacks
+// of type `C`.
+fn insert_listener<E, C>(table: &RefCell<Table>, id: ListenerId, registration: Registration, callback: C)
+where E: 'static, C: Clone + 'static {
+    let Registration { once, priority, owner } = registration;
+
+    let mut table = table.borrow_mut();
+    let handlers = match table.entry(TypeId::of::<E>()) {
+        Entry::Occupied(occupied) => occupied.into_mut(),
+        Entry::Vacant(vacant) => vacant.insert(Box::new(Handlers::<C> {
+            name: std::any::type_name::<E>(),
+            list: Rc::new(vec![]),
+        }))
+    };
+
+    let handlers = match handlers.as_any_mut().downcast_mut::<Handlers<C>>() {
+        Some(handlers) => handlers,
+        None => panic!("event registered with more than one type of data")
+    };
+
+    // Keep the list sorted by descending priority, after any listeners
+    // which share this priority.
+    let list = Rc::make_mut(&mut handlers.list);
+    let index = list.iter()
+        .position(|listener| listener.priority < priority)
+        .unwrap_or(list.len());
+    list.insert(index, Listener {
+        id,
+        once: if once { Some(Rc::new(Cell::new(false))) } else { None },
+        owner,
+        priority,
+        callback,
+    });
+}
+
+// Remove listeners for an event, returning the previous handler list to be
+// dropped once `table` is no longer borrowed, see `HandlerList::remove`.
+fn remove_listeners(table: &RefCell<Table>, event: TypeId, ids: &[ListenerId]) -> Option<Box<dyn Any>> {
+    table.borrow_mut().get_mut(&event).and_then(|handlers| handlers.remove(ids))
+}
+
+// Take a snapshot of the listeners for an event.
+fn snapshot<E, C>(table: &RefCell<Table>) -> Option<Rc<Vec<Listener<C>>>>
+where E: 'static, C: Clone + 'static {
+    table.borrow().get(&TypeId::of::<E>()).map(|handlers| {
+        match handlers.as_any().downcast_ref::<Handlers<C>>() {
+            Some(handlers) => handlers.list.clone(),
+            None => panic!("event triggered with a different type of data than registered")
+        }
+    })
+}
+
+// An event waiting in the queue of an EventEmitter, with its owned data.
+struct Queued {
+    event: TypeId,
+    // Increases through the queue, in the order events were first queued.
+    seq: usize,
+    data: Box<dyn Any>,
+    dispatch: fn(&EventEmitter, Box<dyn Any>),
+}
+
+// How queued events of one type are combined. `Merge` holds a `Merge<X>` and
+// `Unique` an `fn(&X, &X) -> bool` for the event's data type.
+#[derive(Clone)]
+enum Coalesce {
+    Latest,
+    Merge(Rc<dyn Any>),
+    Unique(Rc<dyn Any>),
+}
+
+type Merge<X> = Box<dyn Fn(&mut X, X)>;
+
+fn queued_data<X: 'static>(queued: &mut Queued) -> &mut X {
+    match queued.data.downcast_mut::<X>() {
+        Some(data) => data,
+        None => panic!("event queued with a different type of data than before")
+    }
+}
+
+fn dispatch_queued<E, X>(emitter: &EventEmitter, data: Box<dyn Any>)
+where E: Event<X>, X: 'static {
+    match data.downcast::<X>() {
+        Ok(data) => { emitter.trigger::<E, X>(&data); },
+        Err(_) => unreachable!("queued event data of the wrong type")
+    }
+}
+
+type PanicHook = Rc<dyn Fn(ListenerId, Box<dyn Any + Send>)>;
+
+type AnyCallback = Rc<dyn Fn(&AnyEvent<'_>)>;
+
+// Dispatches an event to the event it extends.
+type ParentLink<X> = Rc<dyn Fn(&EventEmitter, &X) -> bool>;
+
+// The event another event extends. `link` holds its `ParentLink`.
+struct Parent {
+    event: TypeId,
+    link: Box<dyn Any>,
+}
 
 /// The actual event emitter, it contains a lookup table for events and handlers.
+///
+/// A trigger dispatches to a snapshot of the callbacks registered when it
+/// started, so callbacks may register and remove callbacks, or trigger
+/// further events, on the emitter that called them. Changes made by a
+/// callback apply from the next trigger onwards, including triggers nested
+/// inside the current one.
+///
+/// EventEmitter is for use on a single thread; see `SyncEventEmitter` for an
+/// emitter which can be shared between threads.
 pub struct EventEmitter {
-    events: HashMap<TypeId, Vec<Box<Fn(&()) + Send>>>
+    // Shared with the `Subscription`s of callbacks registered with `on_scoped`.
+    events: Rc<RefCell<Table>>,
+    // Callbacks registered with `on_returning`, which have a result type too.
+    queries: RefCell<Table>,
+    // Callbacks registered with `Node::on_capture` and `Node::on_bubble`.
+    phases: RefCell<Table>,
+    // Callbacks registered with `on_any`, which see every event.
+    wildcards: RefCell<Handlers<AnyCallback>>,
+    // The event each event extends.
+    parents: RefCell<HashMap<TypeId, Parent>>,
+    // Events waiting for `flush`.
+    queue: RefCell<VecDeque<Queued>>,
+    coalescing: RefCell<HashMap<TypeId, Coalesce>>,
+    next_seq: Cell<usize>,
+    panic_hook: RefCell<Option<PanicHook>>,
+    next_id: Cell<usize>,
+}
+
+impl EventEmitter {
+    /// Create an EventEmitter with no registered handlers.
+    pub fn new() -> EventEmitter {
+        EventEmitter {
+            events: Rc::new(RefCell::new(HashMap::new())),
+            queries: RefCell::new(HashMap::new()),
+            phases: RefCell::new(HashMap::new()),
+            wildcards: RefCell::new(Handlers { name: "any", list: Rc::new(vec![]) }),
+            parents: RefCell::new(HashMap::new()),
+            queue: RefCell::new(VecDeque::new()),
+            coalescing: RefCell::new(HashMap::new()),
+            next_seq: Cell::new(0),
+            panic_hook: RefCell::new(None),
+            next_id: Cell::new(0),
+        }
+    }
+
+    /// Catch panics from callbacks, passing them to `hook` and carrying on
+    /// with the remaining callbacks.
+    ///
+    /// The hook receives the panicking callback and the panic's payload. A
+    /// callback which panics neither stops propagation nor contributes a
+    /// result. The panic is still reported by the process-wide panic hook,
+    /// and callbacks should be written knowing that data they were
+    /// modifying may be left half-updated.
+    pub fn isolate_panics<H>(&self, hook: H)
+    where H: Fn(ListenerId, Box<dyn Any + Send>) + 'static {
+        *self.panic_hook.borrow_mut() = Some(Rc::new(hook));
+    }
+
+    // Call a callback, catching its panics if panics are isolated.
+    fn call<R>(&self, id: ListenerId, callback: impl FnOnce() -> R) -> Option<R> {
+        // Cloned out, so callbacks and the hook itself may replace the hook.
+        let hook = self.panic_hook.borrow().clone();
+        let hook = match hook {
+            Some(hook) => hook,
+            None => return Some(callback())
+        };
+
+        match panic::catch_unwind(AssertUnwindSafe(callback)) {
+            Ok(result) => Some(result),
+            Err(payload) => { hook(id, payload); None }
+        }
+    }
+
+    /// The number of callbacks registered for an event.
+    ///
+    /// This counts every kind of callback registered for the event itself,
+    /// but not those registered with `on_any` or for an event it extends; see
+    /// `has_listeners`.
+    pub fn listener_count<E: 'static>(&self) -> usize {
+        self.count(TypeId::of::<E>())
+    }
+
+    /// Whether triggering an event would reach any callbacks.
+    ///
+    /// Unlike `listener_count`, this includes callbacks registered with
+    /// `on_any` and those registered for the events it extends.
+    pub fn has_listeners<E: 'static>(&self) -> bool {
+        if self.wildcards.borrow().len() != 0 { return true }
+
+        let parents = self.parents.borrow();
+        let mut event = Some(TypeId::of::<E>());
+        while let Some(current) = event {
+            if self.count(current) != 0 { return true }
+            event = parents.get(&current).map(|parent| parent.event);
+        }
+
+        false
+    }
+
+    /// The events which have callbacks registered, with their type names,
+    /// sorted by name.
+    pub fn event_types(&self) -> Vec<(TypeId, &'static str)> {
+        let mut types = vec![];
+
+        for table in [&*self.events, &self.queries, &self.phases] {
+            for (&event, handlers) in table.borrow().iter() {
+                if handlers.len() != 0 && !types.iter().any(|&(seen, _)| seen == event) {
+                    types.push((event, handlers.name()));
+                }
+            }
+        }
+
+        types.sort_by_key(|&(_, name)| name);
+        types
+    }
+
+    /// Trigger every queued event, in the order they were queued.
+    ///
+    /// Events queued by callbacks during the flush are triggered by the same
+    /// flush, after those already in the queue. Returns the number of events
+    /// triggered.
+    pub fn flush(&self) -> usize {
+        let mut count = 0;
+
+        loop {
+            // The queue must not be borrowed while callbacks run.
+            let queued = self.queue.borrow_mut().pop_front();
+            let queued = match queued {
+                Some(queued) => queued,
+                None => return count
+            };
+
+            (queued.dispatch)(self, queued.data);
+            count += 1;
+        }
+    }
+
+    fn count(&self, event: TypeId) -> usize {
+        [&*self.events, &self.queries, &self.phases].iter()
+            .filter_map(|table| table.borrow().get(&event).map(|handlers| handlers.len()))
+            .sum()
+    }
+
+    fn next_id(&self) -> ListenerId {
+        let id = ListenerId(self.next_id.get());
+        self.next_id.set(id.0 + 1);
+        id
+    }
+
+    fn register<E, X>(&self, registration: Registration, callback: Callback<X>) -> ListenerId
+    where E: Event<X>, X: ?Sized + 'static {
+        self.insert::<E, _>(&self.events, registration, callback)
+    }
+
+    fn insert<E, C>(&self, table: &RefCell<Table>, registration: Registration,
+                    callback: C) -> ListenerId
+    where E: 'static, C: Clone + 'static {
+        let id = self.next_id();
+        insert_listener::<E, C>(table, id, registration, callback);
+        id
+    }
+
+    fn remove<E: 'static>(&self, ids: &[ListenerId]) -> bool {
+        let mut removed = vec![];
+
+        for table in [&*self.events, &self.queries, &self.phases] {
+            removed.extend(remove_listeners(table, TypeId::of::<E>(), ids));
+        }
+
+        // The removed listeners are dropped after every table is released.
+        !removed.is_empty()
+    }
+
+    // Call the callbacks for an event, followed by those for the event it
+    // extends.
+    fn dispatch<E, X>(&self, event: &X) -> bool
+    where E: Event<X>, X: ?Sized + 'static {
+        let mut spent = vec![];
+        let mut stopped = false;
+
+        if let Some(list) = self.listeners::<E, X>() {
+            for listener in list.iter() {
+                let callback = match listener.callback {
+                    Callback::Ref(ref callback) => callback,
+                    Callback::Mut(_) => continue
+                };
+
+                if !listener.claim(&mut spent) { continue }
+
+                if self.call(listener.id, || callback(event)) == Some(Propagation::Stop) {
+                    stopped = true;
+                    break;
+                }
+            }
+        }
+
+        if !spent.is_empty() { self.remove::<E>(&spent); }
+        if stopped { return true }
+
+        let parent = match self.parents.borrow().get(&TypeId::of::<E>()) {
+            Some(parent) => match parent.link.downcast_ref::<ParentLink<X>>() {
+                Some(parent) => parent.clone(),
+                None => panic!("event triggered with a different type of data than extended")
+            },
+            None => return false
+        };
+
+        parent(self, event)
+    }
+
+    // Tell the callbacks registered with `on_any` about an event.
+    fn notify_any<E, X>(&self, event: &X)
+    where E: Event<X>, X: ?Sized + 'static {
+        let list = self.wildcards.borrow().list.clone();
+        if list.is_empty() { return }
+
+        let data: *const X = event;
+        let event = AnyEvent::new::<E, X>(&data);
+
+        for listener in list.iter() {
+            self.call(listener.id, || (listener.callback)(&event));
+        }
+    }
+
+    fn listeners<E, X>(&self) -> Option<Rc<Vec<Listener<Callback<X>>>>>
+    where E: Event<X>, X: ?Sized + 'static {
+        snapshot::<E, _>(&self.events)
+    }
+
+    fn try_dispatch<E, X, Err>(&self, event: &X, fail_fast: bool) -> Result<(), TriggerError<Err>>
+    where E: Event<X>, X: ?Sized + 'static, Err: 'static {
+        self.notify_any::<E, X>(event);
+
+        let list = match snapshot::<E, Rc<dyn Fn(&X) -> Result<(), Err>>>(&self.queries) {
+            Some(list) => list,
+            None => return Ok(())
+        };
+
+        let mut failures = vec![];
+        let mut panicked = vec![];
+
+        for listener in list.iter() {
+            match self.call(listener.id, || (listener.callback)(event)) {
+                Some(Ok(())) => continue,
+                Some(Err(err)) => failures.push((listener.id, err)),
+                None => panicked.push(listener.id)
+            }
+
+            if fail_fast { break }
+        }
+
+        if failures.is_empty() && panicked.is_empty() { return Ok(()) }
+        Err(TriggerError { failures, panicked })
+    }
+}
+
+// Adapt a callback which cannot stop propagation.
+fn continuing<F, X>(callback: F) -> impl Fn(&X) -> Propagation
+where F: Fn(&X), X: ?Sized {
+    move |event| {
+        callback(event);
+        Propagation::Continue
+    }
+}
+
+// Adapt a callback which cannot stop propagation, and modifies the event data.
+fn continuing_mut<F, X>(callback: F) -> impl Fn(&mut X) -> Propagation
+where F: Fn(&mut X), X: ?Sized {
+    move |event| {
+        callback(event);
+        Propagation::Continue
+    }
+}
+
+// Adapt the callback of a forwarding listener so it is skipped while it is
+// already forwarding, which ends a cycle of emitters forwarding to each other
+// once the event comes back round to it.
+fn forwarding<F, T, X>(forward: F) -> impl Fn(&Rc<T>, &X)
+where F: Fn(&Rc<T>, &X), X: ?Sized {
+    struct Active<'a>(&'a Cell<bool>);
+
+    impl Drop for Active<'_> {
+        fn drop(&mut self) { self.0.set(false) }
+    }
+
+    let active = Cell::new(false);
+    move |target, event| {
+        if active.replace(true) { return }
+        let _active = Active(&active);
+        forward(target, event)
+    }
+}
+
+impl fmt::Debug for EventEmitter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let events: BTreeMap<_, _> = self.event_types().into_iter()
+            .map(|(event, name)| (name, self.count(event)))
+            .collect();
+
+        f.debug_struct("EventEmitter")
+            .field("events", &events)
+            .field("any", &self.wildcards.borrow().len())
+            .field("queued", &self.queue.borrow().len())
+            .finish()
+    }
+}
+
+impl Default for EventEmitter {
+    fn default() -> EventEmitter { EventEmitter::new() }
 }
 
 /// Any type that implements Eventable gets `on` and `trigger` methods.
@@ -29,26 +678,436 @@ pub trait Eventable {
 
     /// Register a callback to be fired when an event is triggered.
     ///
-    /// Many callbacks can be registered for a single event.
-    fn on<E: Event<X>, F: Fn(&X) + Send, Sized? X>(&mut self, callback: F) {
-        let callback: Box<Fn(&X) + Send> = box callback;
-        let callback: Box<Fn(&()) + Send> = unsafe { mem::transmute(callback) };
+    /// Many callbacks can be registered for a single event. The returned
+    /// `ListenerId` can be passed to `off` to remove the callback.
+    fn on<E, F, X>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) + 'static, X: ?Sized + 'static {
+        let callback = Callback::Ref(Rc::new(continuing(callback)));
+        self.events().register::<E, X>(Registration::default(), callback)
+    }
 
-        match self.events_mut().events.entry(TypeId::of::<E>()) {
-            Entry::Occupied(mut occupied) => { occupied.get_mut().push(callback); },
-            Entry::Vacant(vacant) => { vacant.set(vec![callback]); }
-        };
+    /// Register a callback which is removed when the returned `Subscription`
+    /// is dropped.
+    fn on_scoped<E, F, X>(&self, callback: F) -> Subscription
+    where E: Event<X>, F: Fn(&X) + 'static, X: ?Sized + 'static {
+        let emitter = self.events();
+        let id = emitter.on::<E, F, X>(callback);
+
+        Subscription { table: Rc::downgrade(&emitter.events), event: TypeId::of::<E>(), id }
+    }
+
+    /// Start building a callback which filters, converts or limits the
+    /// events it sees, registered by `Subscriber::handle`.
+    fn subscribe<E, X>(&self) -> Subscriber<'_, E, X, X>
+    where E: Event<X>, X: ?Sized + 'static {
+        Subscriber::new(self.events())
+    }
+
+    /// Register a callback which is only kept while `owner` is alive.
+    ///
+    /// The emitter holds only a weak reference to the owner, which is passed
+    /// to the callback when it fires. Once the owner has been dropped, the
+    /// callback is removed by the next trigger of the event.
+    fn on_weak<E, F, X, T>(&self, owner: &Rc<T>, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&Rc<T>, &X) + 'static, X: ?Sized + 'static, T: 'static {
+        let weak = Rc::downgrade(owner);
+        let owner = Some(weak.clone() as Weak<dyn Any>);
+        let registration = Registration { owner, ..Registration::default() };
+
+        let callback = continuing(move |event: &X| {
+            if let Some(owner) = weak.upgrade() { callback(&owner, event) }
+        });
+
+        self.events().register::<E, X>(registration, Callback::Ref(Rc::new(callback)))
+    }
+
+    /// Register a callback which may modify the event data.
+    ///
+    /// The callback is only fired by `trigger_mut`, and sees any changes made
+    /// by the callbacks before it.
+    fn on_mut<E, F, X>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&mut X) + 'static, X: ?Sized + 'static {
+        let callback = Callback::Mut(Rc::new(continuing_mut(callback)));
+        self.events().register::<E, X>(Registration::default(), callback)
+    }
+
+    /// Register a callback which decides whether the event propagates any
+    /// further.
+    ///
+    /// When the callback returns `Propagation::Stop`, the remaining callbacks
+    /// for this trigger are skipped.
+    fn on_propagating<E, F, X>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) -> Propagation + 'static, X: ?Sized + 'static {
+        self.events().register::<E, X>(Registration::default(), Callback::Ref(Rc::new(callback)))
+    }
+
+    /// Register a callback with a priority.
+    ///
+    /// Callbacks with a higher priority are called first, callbacks with the
+    /// same priority in the order they were registered. `on` uses priority 0.
+    fn on_with_priority<E, F, X>(&self, priority: i32, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) + 'static, X: ?Sized + 'static {
+        let callback = Callback::Ref(Rc::new(continuing(callback)));
+        let registration = Registration { priority, ..Registration::default() };
+        self.events().register::<E, X>(registration, callback)
+    }
+
+    /// Register a callback to be fired only the next time an event is triggered.
+    ///
+    /// The callback is removed after its first invocation, but can also be
+    /// removed beforehand with `off`.
+    fn once<E, F, X>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) + 'static, X: ?Sized + 'static {
+        let callback = Callback::Ref(Rc::new(continuing(callback)));
+        self.events().register::<E, X>(Registration { once: true, ..Registration::default() }, callback)
+    }
+
+    /// Register a callback which returns a result to the trigger.
+    ///
+    /// The callback is only fired by `trigger_collect` and `trigger_fold`, and
+    /// each event should only be used with a single type of result.
+    fn on_returning<E, F, X, R>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) -> R + 'static, X: ?Sized + 'static, R: 'static {
+        let emitter = self.events();
+
+        let mismatched = emitter.queries.borrow().get(&TypeId::of::<E>())
+            .is_some_and(|handlers| !handlers.as_any().is::<Handlers<Rc<dyn Fn(&X) -> R>>>());
+        if mismatched {
+            panic!("event registered with more than one type of data or result, \
+                    where try_on callbacks return Result<(), Err>")
+        }
+
+        let callback: Rc<dyn Fn(&X) -> R> = Rc::new(callback);
+        emitter.insert::<E, _>(&emitter.queries, Registration::default(), callback)
+    }
+
+    /// Register a callback which can fail.
+    ///
+    /// The callback is only fired by `try_trigger` and `try_trigger_all`. It
+    /// shares its results with `on_returning`, so is also fired by
+    /// `trigger_collect::<E, X, Result<(), Err>>`.
+    fn try_on<E, F, X, Err>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) -> Result<(), Err> + 'static, X: ?Sized + 'static,
+          Err: 'static {
+        self.on_returning::<E, F, X, Result<(), Err>>(callback)
+    }
+
+    /// Register a callback to be fired whenever any event is triggered.
+    ///
+    /// The callback is called before the callbacks for the event itself, by
+    /// every kind of trigger.
+    ///
+    /// The event data is only borrowed for the duration of the call, so the
+    /// callback cannot keep hold of it:
+    ///
+    /// ```compile_fail
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use emitter::{EventEmitter, Eventable};
+    ///
+    /// let emitter = EventEmitter::new();
+    /// let kept: Rc<RefCell<Option<&'static u32>>> = Rc::new(RefCell::new(None));
+    ///
+    /// let inner = kept.clone();
+    /// emitter.on_any(move |event| *inner.borrow_mut() = event.downcast_ref::<u32>());
+    /// ```
+    fn on_any<F>(&self, callback: F) -> ListenerId
+    where F: Fn(&AnyEvent<'_>) + 'static {
+        let emitter = self.events();
+        let id = emitter.next_id();
+
+        let mut wildcards = emitter.wildcards.borrow_mut();
+        Rc::make_mut(&mut wildcards.list).push(Listener {
+            id,
+            once: None,
+            owner: None,
+            priority: 0,
+            callback: Rc::new(callback) as AnyCallback,
+        });
+
+        id
+    }
+
+    /// Declare that the event `E` extends the event `P`.
+    ///
+    /// Triggering `E` then also calls the callbacks for `P`, with the data
+    /// given by `upcast`, once those for `E` itself have been called. `upcast`
+    /// may borrow the data for `P` from that of `E`, or build it anew. `P` may
+    /// extend a further event in turn. An event extends at most one other, so
+    /// extending it again replaces the previous declaration.
+    ///
+    /// Only `trigger`, and so `EventEmitter::flush`, follows the declaration.
+    /// The other triggers only call the callbacks for `E` itself.
+    ///
+    /// The declaration belongs to this emitter alone. Any other emitter which
+    /// should follow it, including the targets of `forward` and `pipe`, has
+    /// to make the same declaration.
+    ///
+    /// Panics if `P` already extends `E`, directly or through other events.
+    fn extend<E, X, P, PX, F>(&self, upcast: F)
+    where E: Event<X>, X: ?Sized + 'static, P: Event<PX>, PX: ToOwned + ?Sized + 'static,
+          F: Fn(&X) -> Cow<'_, PX> + 'static {
+        let mut parents = self.events().parents.borrow_mut();
+
+        let mut ancestor = Some(TypeId::of::<P>());
+        while let Some(event) = ancestor {
+            if event == TypeId::of::<E>() { panic!("event extends itself through its parent") }
+            ancestor = parents.get(&event).map(|parent| parent.event);
+        }
+
+        let link: ParentLink<X> = Rc::new(move |emitter: &EventEmitter, event: &X| {
+            emitter.dispatch::<P, PX>(&upcast(event))
+        });
+
+        parents.insert(TypeId::of::<E>(), Parent { event: TypeId::of::<P>(), link: Box::new(link) });
+    }
+
+    /// Forward an event to another emitter, triggering it there with the
+    /// same data whenever it is triggered here.
+    ///
+    /// Like `on_weak`, only a weak reference to `target` is kept, and the
+    /// forwarding stops once it has been dropped. The returned `ListenerId`
+    /// can be passed to `off` to stop forwarding sooner.
+    ///
+    /// An event which is forwarded back here, directly or through other
+    /// emitters, is triggered again but not forwarded a second time, so
+    /// emitters may forward to each other or to themselves.
+    fn forward<E, X, T>(&self, target: &Rc<T>) -> ListenerId
+    where E: Event<X>, X: ?Sized + 'static, T: Eventable + 'static {
+        self.on_weak::<E, _, X, T>(target, forwarding(|target: &Rc<T>, event: &X| {
+            target.trigger::<E, X>(event);
+        }))
+    }
+
+    /// Forward an event to another emitter as the event `G`, converting its
+    /// data with `map`.
+    ///
+    /// Events for which `map` returns None are not forwarded. Otherwise this
+    /// behaves like `forward`.
+    fn pipe<E, F, X, G, Y, T>(&self, target: &Rc<T>, map: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) -> Option<Y> + 'static, X: ?Sized + 'static,
+          G: Event<Y>, Y: 'static, T: Eventable + 'static {
+        self.on_weak::<E, _, X, T>(target, forwarding(move |target: &Rc<T>, event: &X| {
+            if let Some(data) = map(event) { target.trigger::<G, Y>(&data); }
+        }))
+    }
+
+    /// Remove a callback previously registered for this event with `on`.
+    ///
+    /// Returns false if no such callback was registered.
+    fn off<E, X>(&self, id: ListenerId) -> bool
+    where E: Event<X>, X: ?Sized + 'static {
+        self.events().remove::<E>(&[id])
+    }
+
+    /// Remove a callback previously registered with `on_any`.
+    ///
+    /// Returns false if no such callback was registered.
+    fn off_any(&self, id: ListenerId) -> bool {
+        let removed = self.events().wildcards.borrow_mut().remove(&[id]);
+        removed.is_some()
     }
 
     /// Trigger an event, calling all of the associated handlers.
-    fn trigger<E: Event<X>, Sized? X>(&self, event: &X) {
-        self.events().events.get(&TypeId::of::<E>())
-            .map(|handlers| unsafe { mem::transmute(handlers) })
-            .map(move |handlers: &Vec<Box<Fn(&X)>>| {
-                for handler in handlers.iter() {
-                    handler.call((event,))
-                }
+    ///
+    /// Callbacks registered with `on_mut` are not called. If the event
+    /// extends another, see `extend`, the callbacks for that event are called
+    /// afterwards unless propagation was stopped.
+    ///
+    /// Returns true if a handler stopped propagation of the event.
+    fn trigger<E, X>(&self, event: &X) -> bool
+    where E: Event<X>, X: ?Sized + 'static {
+        let emitter = self.events();
+        emitter.notify_any::<E, X>(event);
+        emitter.dispatch::<E, X>(event)
+    }
+
+    /// Queue an event, to be triggered by the next `EventEmitter::flush`.
+    ///
+    /// If the event is coalesced, see `coalesce_latest`, `coalesce_with` and
+    /// `coalesce_unique`, the data may instead be combined with data already
+    /// in the queue.
+    fn enqueue<E, X>(&self, event: X)
+    where E: Event<X>, X: 'static {
+        let emitter = self.events();
+        let event_type = TypeId::of::<E>();
+        let coalesce = emitter.coalescing.borrow().get(&event_type).cloned();
+
+        // The entries a merge function or `PartialEq` looks at are taken out
+        // of the queue while it runs, so neither the queue nor the coalescing
+        // policies are borrowed; the rest of the queue stays in place for any
+        // events it enqueues or flushes.
+        let mut taken = {
+            let mut queue = emitter.queue.borrow_mut();
+            let matching = |queued: &Queued| queued.event == event_type;
+
+            match coalesce {
+                Some(Coalesce::Latest) => {
+                    if let Some(queued) = queue.iter_mut().find(|queued| matching(queued)) {
+                        *queued_data::<X>(queued) = event;
+                        return;
+                    }
+                    VecDeque::new()
+                },
+                Some(Coalesce::Merge(_)) => {
+                    queue.iter().position(matching).and_then(|index| queue.remove(index)).into_iter().collect()
+                },
+                Some(Coalesce::Unique(_)) => {
+                    let (taken, rest) = mem::take(&mut *queue).into_iter().partition(matching);
+                    *queue = rest;
+                    taken
+                },
+                None => VecDeque::new()
+            }
+        };
+
+        let event = match (coalesce, taken.front_mut()) {
+            (Some(Coalesce::Merge(merge)), Some(queued)) => {
+                match merge.downcast_ref::<Merge<X>>() {
+                    Some(merge) => merge(queued_data::<X>(queued), event),
+                    None => panic!("event queued with a different type of data than coalesced")
+                };
+                None
+            },
+            (Some(Coalesce::Unique(eq)), Some(_)) => {
+                let eq = match eq.downcast_ref::<fn(&X, &X) -> bool>() {
+                    Some(eq) => eq,
+                    None => panic!("event queued with a different type of data than coalesced")
+                };
+                if taken.iter_mut().any(|queued| eq(queued_data::<X>(queued), &event)) { None } else { Some(event) }
+            },
+            _ => Some(event)
+        };
+
+        // Put the taken entries back in their places, which are found by
+        // their sequence numbers as the queue may have changed meanwhile.
+        let mut queue = emitter.queue.borrow_mut();
+        for queued in taken {
+            let index = queue.partition_point(|other| other.seq < queued.seq);
+            queue.insert(index, queued);
+        }
+        if let Some(event) = event {
+            let seq = emitter.next_seq.get();
+            emitter.next_seq.set(seq + 1);
+            queue.push_back(Queued {
+                event: event_type,
+                seq,
+                data: Box::new(event),
+                dispatch: dispatch_queued::<E, X>,
             });
+        }
+    }
+
+    /// Coalesce queued events of this type, so only the most recently queued
+    /// data is triggered.
+    ///
+    /// The event keeps its place in the queue from when it was first queued.
+    fn coalesce_latest<E, X>(&self)
+    where E: Event<X>, X: 'static {
+        self.events().coalescing.borrow_mut().insert(TypeId::of::<E>(), Coalesce::Latest);
+    }
+
+    /// Coalesce queued events of this type by merging the data of each newly
+    /// queued event into the data already queued.
+    fn coalesce_with<E, X, F>(&self, merge: F)
+    where E: Event<X>, X: 'static, F: Fn(&mut X, X) + 'static {
+        let merge: Merge<X> = Box::new(merge);
+        let merge = Coalesce::Merge(Rc::new(merge));
+        self.events().coalescing.borrow_mut().insert(TypeId::of::<E>(), merge);
+    }
+
+    /// Coalesce queued events of this type by dropping newly queued events
+    /// whose data is equal to that of an event already queued.
+    fn coalesce_unique<E, X>(&self)
+    where E: Event<X>, X: PartialEq + 'static {
+        let eq: fn(&X, &X) -> bool = X::eq;
+        let unique = Coalesce::Unique(Rc::new(eq));
+        self.events().coalescing.borrow_mut().insert(TypeId::of::<E>(), unique);
+    }
+
+    /// Trigger an event with data which the handlers may modify.
+    ///
+    /// Both callbacks registered with `on_mut` and those which only observe
+    /// the data are called, in the usual order. Unlike `trigger`, this does
+    /// not call the callbacks for an event `E` extends.
+    ///
+    /// Returns true if a handler stopped propagation of the event.
+    fn trigger_mut<E, X>(&self, event: &mut X) -> bool
+    where E: Event<X>, X: ?Sized + 'static {
+        let emitter = self.events();
+        emitter.notify_any::<E, X>(event);
+
+        let list = match emitter.listeners::<E, X>() {
+            Some(list) => list,
+            None => return false
+        };
+
+        let mut spent = vec![];
+        let mut stopped = false;
+
+        for listener in list.iter() {
+            if !listener.claim(&mut spent) { continue }
+
+            let propagation = emitter.call(listener.id, || match listener.callback {
+                Callback::Ref(ref callback) => callback(event),
+                Callback::Mut(ref callback) => callback(event)
+            });
+
+            if propagation == Some(Propagation::Stop) {
+                stopped = true;
+                break;
+            }
+        }
+
+        if !spent.is_empty() { emitter.remove::<E>(&spent); }
+
+        stopped
+    }
+
+    /// Trigger an event, collecting the results of the callbacks registered
+    /// with `on_returning`.
+    fn trigger_collect<E, X, R>(&self, event: &X) -> Vec<R>
+    where E: Event<X>, X: ?Sized + 'static, R: 'static {
+        self.trigger_fold::<E, X, R, _, _>(event, vec![], |mut results, result| {
+            results.push(result);
+            results
+        })
+    }
+
+    /// Trigger an event, combining the results of the callbacks registered
+    /// with `on_returning` using `reduce`.
+    fn trigger_fold<E, X, R, A, G>(&self, event: &X, init: A, mut reduce: G) -> A
+    where E: Event<X>, X: ?Sized + 'static, R: 'static, G: FnMut(A, R) -> A {
+        let emitter = self.events();
+        emitter.notify_any::<E, X>(event);
+
+        let list = match snapshot::<E, Rc<dyn Fn(&X) -> R>>(&emitter.queries) {
+            Some(list) => list,
+            None => return init
+        };
+
+        list.iter().fold(init, |acc, listener| {
+            match emitter.call(listener.id, || (listener.callback)(event)) {
+                Some(result) => reduce(acc, result),
+                None => acc
+            }
+        })
+    }
+
+    /// Trigger an event, calling the callbacks registered with `try_on` until
+    /// one of them fails.
+    ///
+    /// When panics are isolated, a callback which panics counts as failed.
+    fn try_trigger<E, X, Err>(&self, event: &X) -> Result<(), TriggerError<Err>>
+    where E: Event<X>, X: ?Sized + 'static, Err: 'static {
+        self.events().try_dispatch::<E, X, Err>(event, true)
+    }
+
+    /// Trigger an event, calling every callback registered with `try_on` and
+    /// returning all of their errors.
+    fn try_trigger_all<E, X, Err>(&self, event: &X) -> Result<(), TriggerError<Err>>
+    where E: Event<X>, X: ?Sized + 'static, Err: 'static {
+        self.events().try_dispatch::<E, X, Err>(event, false)
     }
 }
 
@@ -58,3 +1117,680 @@ impl Eventable for EventEmitter {
     fn events_mut(&mut self) -> &mut EventEmitter { self }
 }
 
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{AnyEvent, Event, EventEmitter, Eventable, Propagation, Subscription, TriggerError};
+
+    struct Click;
+    impl Event<u32> for Click {}
+
+    struct Message;
+    impl Event<str> for Message {}
+
+    struct Both;
+    impl Event<u32> for Both {}
+    impl Event<String> for Both {}
+
+    fn recorder<T>() -> Rc<RefCell<Vec<T>>> {
+        Rc::new(RefCell::new(vec![]))
+    }
+
+    #[test]
+    fn test_trigger_calls_handlers_in_order() {
+        let emitter = EventEmitter::new();
+        let seen = recorder();
+
+        let first = seen.clone();
+        emitter.on::<Click, _, u32>(move |x| first.borrow_mut().push((1, *x)));
+        let second = seen.clone();
+        emitter.on::<Click, _, u32>(move |x| second.borrow_mut().push((2, *x)));
+
+        emitter.trigger::<Click, u32>(&7);
+        assert_eq!(*seen.borrow(), vec![(1, 7), (2, 7)]);
+    }
+
+    #[test]
+    fn test_priority_orders_handlers() {
+        let emitter = EventEmitter::new();
+        let seen = recorder();
+
+        let low = seen.clone();
+        emitter.on_with_priority::<Click, _, u32>(-1, move |_| low.borrow_mut().push("low"));
+        let default = seen.clone();
+        emitter.on::<Click, _, u32>(move |_| default.borrow_mut().push("default"));
+        let first = seen.clone();
+        emitter.on_with_priority::<Click, _, u32>(10, move |_| first.borrow_mut().push("first"));
+        let second = seen.clone();
+        emitter.on_with_priority::<Click, _, u32>(10, move |_| second.borrow_mut().push("second"));
+
+        emitter.trigger::<Click, u32>(&7);
+        assert_eq!(*seen.borrow(), vec!["first", "second", "default", "low"]);
+    }
+
+    #[test]
+    fn test_trigger_without_handlers() {
+        let emitter = EventEmitter::new();
+        assert!(!emitter.trigger::<Click, u32>(&7));
+    }
+
+    #[test]
+    fn test_stop_propagation() {
+        let emitter = EventEmitter::new();
+        let seen = recorder();
+
+        let first = seen.clone();
+        emitter.on_propagating::<Click, _, u32>(move |x| {
+            first.borrow_mut().push(*x);
+            if *x > 5 { Propagation::Stop } else { Propagation::Continue }
+        });
+        let second = seen.clone();
+        emitter.on::<Click, _, u32>(move |x| second.borrow_mut().push(*x * 10));
+
+        assert!(!emitter.trigger::<Click, u32>(&1));
+        assert!(emitter.trigger::<Click, u32>(&7));
+        assert_eq!(*seen.borrow(), vec![1, 10, 7]);
+    }
+
+    #[test]
+    fn test_unsized_data() {
+        let emitter = EventEmitter::new();
+        let seen = recorder();
+
+        let inner = seen.clone();
+        emitter.on::<Message, _, str>(move |s| inner.borrow_mut().push(s.to_string()));
+
+        emitter.trigger::<Message, str>("hello");
+        assert_eq!(*seen.borrow(), vec!["hello".to_string()]);
+    }
+
+    #[test]
+    fn test_off_removes_handler() {
+        let emitter = EventEmitter::new();
+        let seen = recorder();
+
+        let inner = seen.clone();
+        let id = emitter.on::<Click, _, u32>(move |x| inner.borrow_mut().push(*x));
+
+        assert!(emitter.off::<Click, u32>(id));
+        assert!(!emitter.off::<Click, u32>(id));
+
+        emitter.trigger::<Click, u32>(&7);
+        assert!(seen.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_once_fires_a_single_time() {
+        let emitter = EventEmitter::new();
+        let seen = recorder();
+
+        let inner = seen.clone();
+        let id = emitter.once::<Click, _, u32>(move |x| inner.borrow_mut().push(*x));
+
+        emitter.trigger::<Click, u32>(&1);
+        emitter.trigger::<Click, u32>(&2);
+        assert_eq!(*seen.borrow(), vec![1]);
+
+        // The handler has already been removed.
+        assert!(!emitter.off::<Click, u32>(id));
+    }
+
+    #[test]
+    fn test_handlers_can_register_during_trigger() {
+        let emitter = Rc::new(EventEmitter::new());
+        let seen = recorder();
+
+        let (inner, seen_inner) = (emitter.clone(), seen.clone());
+        emitter.once::<Click, _, u32>(move |_| {
+            let seen = seen_inner.clone();
+            inner.on::<Click, _, u32>(move |x| seen.borrow_mut().push(*x));
+        });
+
+        // The new handler only sees triggers after the one that added it.
+        emitter.trigger::<Click, u32>(&1);
+        emitter.trigger::<Click, u32>(&2);
+        assert_eq!(*seen.borrow(), vec![2]);
+    }
+
+    #[test]
+    fn test_handlers_can_remove_during_trigger() {
+        let emitter = Rc::new(EventEmitter::new());
+        let seen = recorder();
+
+        let inner = seen.clone();
+        let id = emitter.on::<Click, _, u32>(move |x| inner.borrow_mut().push(*x));
+
+        // Removal applies from the next trigger, so the removed handler still
+        // sees the trigger which removed it.
+        let other = emitter.clone();
+        emitter.on_with_priority::<Click, _, u32>(1, move |_| { other.off::<Click, u32>(id); });
+
+        emitter.trigger::<Click, u32>(&1);
+        emitter.trigger::<Click, u32>(&2);
+        assert_eq!(*seen.borrow(), vec![1]);
+    }
+
+    #[test]
+    fn test_nested_trigger_sees_registration() {
+        let emitter = Rc::new(EventEmitter::new());
+        let seen = recorder();
+
+        let (inner, seen_inner) = (emitter.clone(), seen.clone());
+        emitter.once::<Click, _, u32>(move |_| {
+            let seen = seen_inner.clone();
+            inner.on::<Message, _, str>(move |s| seen.borrow_mut().push(s.to_string()));
+            inner.trigger::<Message, str>("nested");
+        });
+
+        emitter.trigger::<Click, u32>(&1);
+        assert_eq!(*seen.borrow(), vec!["nested".to_string()]);
+    }
+
+    #[test]
+    fn test_trigger_mut_chains_modifications() {
+        let emitter = EventEmitter::new();
+        let seen = recorder();
+
+        emitter.on_mut::<Click, _, u32>(|x| *x += 1);
+        let inner = seen.clone();
+        emitter.on::<Click, _, u32>(move |x| inner.borrow_mut().push(*x));
+        emitter.on_mut::<Click, _, u32>(|x| *x *= 10);
+
+        let mut data = 1;
+        emitter.trigger_mut::<Click, u32>(&mut data);
+        assert_eq!(data, 20);
+
+        // Plain triggers skip the callbacks which modify the data.
+        emitter.trigger::<Click, u32>(&5);
+        assert_eq!(*seen.borrow(), vec![2, 5]);
+    }
+
+    #[test]
+    fn test_trigger_collect_and_fold() {
+        let emitter = EventEmitter::new();
+
+        emitter.on_returning::<Click, _, u32, bool>(|x| *x > 1);
+        let id = emitter.on_returning::<Click, _, u32, bool>(|x| *x > 5);
+        emitter.on_returning::<Click, _, u32, bool>(|_| true);
+
+        assert_eq!(emitter.trigger_collect::<Click, u32, bool>(&3), vec![true, false, true]);
+
+        let vetoed = emitter.trigger_fold::<Click, u32, bool, _, _>(&3, false, |acc, ok| acc || !ok);
+        assert!(vetoed);
+
+        assert!(emitter.off::<Click, u32>(id));
+        assert_eq!(emitter.trigger_collect::<Click, u32, bool>(&3), vec![true, true]);
+    }
+
+    #[test]
+    fn test_try_trigger() {
+        let emitter = EventEmitter::new();
+        let seen = recorder();
+
+        let inner = seen.clone();
+        emitter.try_on::<Click, _, u32, String>(move |x| {
+            inner.borrow_mut().push(*x);
+            if *x > 1 { Err("too big".to_string()) } else { Ok(()) }
+        });
+        let second = emitter.try_on::<Click, _, u32, String>(|_| Err("always".to_string()));
+
+        assert!(emitter.try_trigger::<Click, u32, String>(&7).is_err());
+        assert_eq!(*seen.borrow(), vec![7]);
+
+        match emitter.try_trigger_all::<Click, u32, String>(&7) {
+            Err(TriggerError { failures, .. }) => {
+                assert_eq!(failures.len(), 2);
+                assert_eq!(failures[1], (second, "always".to_string()));
+            },
+            Ok(()) => panic!("expected both callbacks to fail")
+        }
+
+        let err = emitter.try_trigger::<Click, u32, String>(&1).unwrap_err();
+        assert_eq!(err.failures, vec![(second, "always".to_string())]);
+        assert_eq!(err.to_string(), format!("listener {} failed: always", second.0));
+    }
+
+    #[test]
+    fn test_try_trigger_reports_panics() {
+        let emitter = EventEmitter::new();
+        emitter.isolate_panics(|_, _| {});
+
+        let bad = emitter.try_on::<Click, _, u32, String>(|_| panic!("bad handler"));
+        let failing = emitter.try_on::<Click, _, u32, String>(|_| Err("always".to_string()));
+
+        let err = emitter.try_trigger::<Click, u32, String>(&1).unwrap_err();
+        assert!(err.failures.is_empty());
+        assert_eq!(err.panicked, vec![bad]);
+
+        let err = emitter.try_trigger_all::<Click, u32, String>(&1).unwrap_err();
+        let expected = format!("listener {} failed: always; listener {} panicked", failing.0, bad.0);
+        assert_eq!(err.to_string(), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "more than one type of data or result")]
+    fn test_returning_and_fallible_callbacks_conflict() {
+        let emitter = EventEmitter::new();
+        emitter.on_returning::<Click, _, u32, u32>(|x| *x);
+        emitter.try_on::<Click, _, u32, String>(|_| Ok(()));
+    }
+
+    #[test]
+    fn test_isolate_panics() {
+        let emitter = Rc::new(EventEmitter::new());
+        let seen = recorder();
+        let panics = recorder();
+
+        let inner = panics.clone();
+        emitter.isolate_panics(move |id, payload| {
+            let message = payload.downcast_ref::<&str>().unwrap().to_string();
+            inner.borrow_mut().push((id, message));
+        });
+
+        let bad = emitter.on::<Click, _, u32>(|_| panic!("bad handler"));
+        let inner = seen.clone();
+        emitter.on::<Click, _, u32>(move |x| inner.borrow_mut().push(*x));
+
+        assert!(!emitter.trigger::<Click, u32>(&7));
+        assert_eq!(*seen.borrow(), vec![7]);
+        assert_eq!(*panics.borrow(), vec![(bad, "bad handler".to_string())]);
+    }
+
+    #[test]
+    fn test_on_any_sees_every_event() {
+        let emitter = EventEmitter::new();
+        let seen = recorder();
+
+        let inner = seen.clone();
+        let id = emitter.on_any(move |event: &AnyEvent<'_>| {
+            let data = match event.downcast_ref::<u32>() {
+                Some(x) => x.to_string(),
+                None => event.downcast_ref::<str>().unwrap().to_string()
+            };
+            inner.borrow_mut().push((event.name().rsplit("::").next().unwrap().to_string(), data));
+        });
+
+        emitter.trigger::<Click, u32>(&7);
+        emitter.trigger::<Message, str>("hello");
+        assert!(emitter.off_any(id));
+        emitter.trigger::<Click, u32>(&8);
+
+        assert_eq!(*seen.borrow(), vec![
+            ("Click".to_string(), "7".to_string()),
+            ("Message".to_string(), "hello".to_string())
+        ]);
+    }
+
+    #[test]
+    fn test_extended_events_dispatch_to_parents() {
+        struct Input;
+        impl Event<u32> for Input {}
+
+        struct Key;
+        impl Event<(u32, char)> for Key {}
+
+        struct KeyDown;
+        impl Event<(u32, char)> for KeyDown {}
+
+        let emitter = EventEmitter::new();
+        let seen = recorder();
+
+        emitter.extend::<KeyDown, (u32, char), Key, (u32, char), _>(|key| Cow::Borrowed(key));
+        emitter.extend::<Key, (u32, char), Input, u32, _>(|key| Cow::Borrowed(&key.0));
+
+        let inner = seen.clone();
+        emitter.on::<Input, _, u32>(move |time| inner.borrow_mut().push(format!("input {}", time)));
+        let inner = seen.clone();
+        emitter.on::<Key, _, (u32, char)>(move |key| inner.borrow_mut().push(format!("key {}", key.1)));
+        let inner = seen.clone();
+        emitter.on::<KeyDown, _, (u32, char)>(move |key| inner.borrow_mut().push(format!("down {}", key.1)));
+
+        assert!(!emitter.trigger::<KeyDown, (u32, char)>(&(3, 'a')));
+        assert_eq!(*seen.borrow(), vec!["down a", "key a", "input 3"]);
+
+        // Stopping propagation for the child skips its parents.
+        seen.borrow_mut().clear();
+        emitter.on_propagating::<KeyDown, _, (u32, char)>(|_| Propagation::Stop);
+        assert!(emitter.trigger::<KeyDown, (u32, char)>(&(4, 'b')));
+        assert_eq!(*seen.borrow(), vec!["down b"]);
+    }
+
+    #[test]
+    fn test_extend_may_build_parent_data() {
+        #[derive(Clone, Debug, PartialEq)]
+        struct Pointer { x: i32, y: i32, device: &'static str }
+
+        struct Move;
+        impl Event<Pointer> for Move {}
+
+        struct Touch;
+        impl Event<(i32, i32)> for Touch {}
+
+        let emitter = EventEmitter::new();
+        let seen = Rc::new(RefCell::new(vec![]));
+
+        let device = "touchscreen";
+        emitter.extend::<Touch, (i32, i32), Move, Pointer, _>(move |&(x, y)| {
+            Cow::Owned(Pointer { x, y, device })
+        });
+
+        let inner = seen.clone();
+        emitter.on::<Move, _, Pointer>(move |pointer| inner.borrow_mut().push(pointer.clone()));
+
+        emitter.trigger::<Touch, (i32, i32)>(&(3, 4));
+        assert_eq!(*seen.borrow(), vec![Pointer { x: 3, y: 4, device: "touchscreen" }]);
+    }
+
+    #[test]
+    #[should_panic(expected = "event extends itself")]
+    fn test_extend_cycle_panics() {
+        struct Key;
+        impl Event<u32> for Key {}
+
+        struct KeyDown;
+        impl Event<u32> for KeyDown {}
+
+        let emitter = EventEmitter::new();
+        emitter.extend::<KeyDown, u32, Key, u32, _>(|key| Cow::Borrowed(key));
+        emitter.extend::<Key, u32, KeyDown, u32, _>(|key| Cow::Borrowed(key));
+    }
+
+    #[test]
+    fn test_enqueue_and_flush() {
+        let emitter = Rc::new(EventEmitter::new());
+        let seen = recorder();
+
+        let (inner, seen_inner) = (emitter.clone(), seen.clone());
+        emitter.on::<Click, _, u32>(move |x| {
+            seen_inner.borrow_mut().push(*x);
+            if *x == 1 { inner.enqueue::<Click, u32>(10); }
+        });
+
+        emitter.enqueue::<Click, u32>(1);
+        emitter.enqueue::<Click, u32>(2);
+        assert!(seen.borrow().is_empty());
+
+        assert_eq!(emitter.flush(), 3);
+        assert_eq!(*seen.borrow(), vec![1, 2, 10]);
+        assert_eq!(emitter.flush(), 0);
+    }
+
+    #[test]
+    fn test_coalesce_queued_events() {
+        struct Resize;
+        impl Event<(u32, u32)> for Resize {}
+
+        struct Scroll;
+        impl Event<i32> for Scroll {}
+
+        let emitter = EventEmitter::new();
+        let seen = recorder();
+
+        emitter.coalesce_latest::<Resize, (u32, u32)>();
+        emitter.coalesce_with::<Scroll, i32, _>(|total, delta| *total += delta);
+        emitter.coalesce_unique::<Click, u32>();
+
+        let inner = seen.clone();
+        emitter.on::<Resize, _, (u32, u32)>(move |size| inner.borrow_mut().push(format!("resize {:?}", size)));
+        let inner = seen.clone();
+        emitter.on::<Scroll, _, i32>(move |delta| inner.borrow_mut().push(format!("scroll {}", delta)));
+        let inner = seen.clone();
+        emitter.on::<Click, _, u32>(move |x| inner.borrow_mut().push(format!("click {}", x)));
+
+        emitter.enqueue::<Resize, (u32, u32)>((1, 1));
+        emitter.enqueue::<Scroll, i32>(3);
+        emitter.enqueue::<Click, u32>(1);
+        emitter.enqueue::<Resize, (u32, u32)>((2, 2));
+        emitter.enqueue::<Scroll, i32>(-1);
+        emitter.enqueue::<Click, u32>(2);
+        emitter.enqueue::<Click, u32>(1);
+
+        assert_eq!(emitter.flush(), 4);
+        assert_eq!(*seen.borrow(), vec!["resize (2, 2)", "scroll 2", "click 1", "click 2"]);
+    }
+
+    #[test]
+    fn test_merge_may_enqueue() {
+        struct Resize;
+        impl Event<u32> for Resize {}
+
+        let emitter = Rc::new(EventEmitter::new());
+        let seen = recorder();
+
+        let inner = emitter.clone();
+        emitter.coalesce_with::<Resize, u32, _>(move |size, next| {
+            *size = next;
+            inner.enqueue::<Click, u32>(next);
+        });
+
+        let inner = seen.clone();
+        emitter.on::<Resize, _, u32>(move |size| inner.borrow_mut().push(format!("resize {}", size)));
+        let inner = seen.clone();
+        emitter.on::<Click, _, u32>(move |x| inner.borrow_mut().push(format!("click {}", x)));
+
+        emitter.enqueue::<Resize, u32>(1);
+        emitter.enqueue::<Resize, u32>(2);
+        emitter.flush();
+        assert_eq!(*seen.borrow(), vec!["resize 2", "click 2"]);
+    }
+
+    #[test]
+    fn test_merge_may_enqueue_coalesced_events() {
+        struct Resize;
+        impl Event<u32> for Resize {}
+
+        let emitter = Rc::new(EventEmitter::new());
+        let seen = recorder();
+        let flushed = recorder();
+
+        emitter.coalesce_latest::<Click, u32>();
+        let (inner, count) = (emitter.clone(), flushed.clone());
+        emitter.coalesce_with::<Resize, u32, _>(move |size, next| {
+            *size = next;
+            inner.enqueue::<Click, u32>(next);
+            if next == 3 { count.borrow_mut().push(inner.flush()) }
+        });
+
+        let inner = seen.clone();
+        emitter.on::<Resize, _, u32>(move |size| inner.borrow_mut().push(format!("resize {}", size)));
+        let inner = seen.clone();
+        emitter.on::<Click, _, u32>(move |x| inner.borrow_mut().push(format!("click {}", x)));
+
+        emitter.enqueue::<Click, u32>(1);
+        emitter.enqueue::<Resize, u32>(1);
+        emitter.enqueue::<Resize, u32>(2);
+        assert_eq!(emitter.flush(), 2);
+        assert_eq!(*seen.borrow(), vec!["click 2", "resize 2"]);
+
+        // A flush from the merge function sees the rest of the queue, and the
+        // merged event is put back at the front.
+        seen.borrow_mut().clear();
+        emitter.enqueue::<Resize, u32>(2);
+        emitter.enqueue::<Click, u32>(1);
+        emitter.enqueue::<Resize, u32>(3);
+        assert_eq!(*flushed.borrow(), vec![1]);
+        assert_eq!(emitter.flush(), 1);
+        assert_eq!(*seen.borrow(), vec!["click 3", "resize 3"]);
+    }
+
+    #[test]
+    fn test_introspection() {
+        let emitter = EventEmitter::new();
+        assert!(!emitter.has_listeners::<Click>());
+
+        let id = emitter.on::<Click, _, u32>(|_| {});
+        emitter.on_returning::<Click, _, u32, bool>(|_| true);
+        emitter.on::<Message, _, str>(|_| {});
+        emitter.on_any(|_| {});
+
+        assert_eq!(emitter.listener_count::<Click>(), 2);
+        assert!(emitter.has_listeners::<Message>());
+        assert_eq!(emitter.event_types().len(), 2);
+        assert!(emitter.event_types()[0].1.ends_with("Click"));
+
+        let debug = format!("{:?}", emitter);
+        assert!(debug.contains("Click\": 2"), "{}", debug);
+        assert!(debug.contains("any: 1"), "{}", debug);
+
+        emitter.off::<Click, u32>(id);
+        assert_eq!(emitter.listener_count::<Click>(), 1);
+    }
+
+    #[test]
+    fn test_has_listeners_counts_wildcards() {
+        let emitter = EventEmitter::new();
+        let id = emitter.on_any(|_| {});
+
+        assert!(emitter.has_listeners::<Click>());
+        assert_eq!(emitter.listener_count::<Click>(), 0);
+
+        emitter.off_any(id);
+        assert!(!emitter.has_listeners::<Click>());
+    }
+
+    #[test]
+    fn test_has_listeners_follows_extend() {
+        struct Key;
+        impl Event<u32> for Key {}
+
+        struct KeyDown;
+        impl Event<u32> for KeyDown {}
+
+        let emitter = EventEmitter::new();
+        emitter.extend::<KeyDown, u32, Key, u32, _>(|key| Cow::Borrowed(key));
+        emitter.extend::<Key, u32, Click, u32, _>(|key| Cow::Borrowed(key));
+        assert!(!emitter.has_listeners::<KeyDown>());
+
+        emitter.on::<Click, _, u32>(|_| {});
+        assert!(emitter.has_listeners::<KeyDown>());
+        assert_eq!(emitter.listener_count::<KeyDown>(), 0);
+        assert!(!emitter.has_listeners::<Message>());
+    }
+
+    #[test]
+    fn test_weak_listener_is_pruned_with_its_owner() {
+        let emitter = EventEmitter::new();
+        let owner = Rc::new(RefCell::new(vec![]));
+
+        emitter.on_weak::<Click, _, u32, _>(&owner, |owner, x| owner.borrow_mut().push(*x));
+
+        emitter.trigger::<Click, u32>(&1);
+        assert_eq!(*owner.borrow(), vec![1]);
+        assert_eq!(emitter.listener_count::<Click>(), 1);
+
+        drop(owner);
+        assert!(!emitter.has_listeners::<Click>());
+        emitter.trigger::<Click, u32>(&2);
+        assert_eq!(emitter.listener_count::<Click>(), 0);
+    }
+
+    #[test]
+    fn test_subscription_removes_on_drop() {
+        let emitter = EventEmitter::new();
+
+        let subscription = emitter.on_scoped::<Click, _, u32>(|_| {});
+        let kept = emitter.on_scoped::<Click, _, u32>(|_| {}).forget();
+        assert_eq!(emitter.listener_count::<Click>(), 2);
+
+        drop(subscription);
+        assert_eq!(emitter.listener_count::<Click>(), 1);
+        assert!(emitter.off::<Click, u32>(kept));
+
+        // Outliving the emitter is harmless.
+        let subscription: Subscription = emitter.on_scoped::<Click, _, u32>(|_| {});
+        drop(emitter);
+        drop(subscription);
+    }
+
+    #[test]
+    fn test_forward_and_pipe() {
+        struct Summary;
+        impl Event<String> for Summary {}
+
+        let source = EventEmitter::new();
+        let target = Rc::new(EventEmitter::new());
+        let seen = recorder();
+
+        let inner = seen.clone();
+        target.on::<Click, _, u32>(move |x| inner.borrow_mut().push(format!("click {}", x)));
+        let inner = seen.clone();
+        target.on::<Summary, _, String>(move |s| inner.borrow_mut().push(format!("summary {}", s)));
+
+        let id = source.forward::<Click, u32, _>(&target);
+        source.pipe::<Click, _, u32, Summary, String, _>(&target, |x| {
+            if *x > 1 { Some(format!("{} clicks", x)) } else { None }
+        });
+
+        source.trigger::<Click, u32>(&1);
+        source.trigger::<Click, u32>(&2);
+        assert!(source.off::<Click, u32>(id));
+        source.trigger::<Click, u32>(&3);
+        assert_eq!(*seen.borrow(), vec!["click 1", "click 2", "summary 2 clicks", "summary 3 clicks"]);
+
+        // Forwarding stops once the target is dropped.
+        drop(target);
+        source.trigger::<Click, u32>(&4);
+        assert!(!source.has_listeners::<Click>());
+    }
+
+    #[test]
+    fn test_forwarding_cycles_end() {
+        let first = Rc::new(EventEmitter::new());
+        let second = Rc::new(EventEmitter::new());
+        let seen = recorder();
+
+        let inner = seen.clone();
+        first.on::<Click, _, u32>(move |x| inner.borrow_mut().push(format!("first {}", x)));
+        let inner = seen.clone();
+        second.on::<Click, _, u32>(move |x| inner.borrow_mut().push(format!("second {}", x)));
+
+        first.forward::<Click, u32, _>(&second);
+        second.pipe::<Click, _, u32, Click, u32, _>(&first, |x| Some(x + 1));
+        first.trigger::<Click, u32>(&1);
+        assert_eq!(*seen.borrow(), vec!["first 1", "second 1", "first 2"]);
+
+        // An emitter may forward to itself.
+        let emitter = Rc::new(EventEmitter::new());
+        let count = recorder();
+        let inner = count.clone();
+        emitter.on::<Click, _, u32>(move |x| inner.borrow_mut().push(*x));
+        emitter.forward::<Click, u32, _>(&emitter);
+        emitter.trigger::<Click, u32>(&1);
+        assert_eq!(*count.borrow(), vec![1, 1]);
+    }
+
+    #[test]
+    fn test_subscription_owned_by_a_removed_handler() {
+        let emitter = EventEmitter::new();
+
+        let subscription = emitter.on_scoped::<Click, _, u32>(|_| {});
+        let id = emitter.on::<Click, _, u32>(move |_| { let _ = subscription.id(); });
+        assert!(emitter.off::<Click, u32>(id));
+        assert_eq!(emitter.listener_count::<Click>(), 0);
+
+        let subscription = emitter.on_scoped::<Message, _, str>(|_| {});
+        emitter.once::<Click, _, u32>(move |_| { let _ = subscription.id(); });
+        emitter.trigger::<Click, u32>(&1);
+        assert_eq!(emitter.listener_count::<Click>(), 0);
+        assert_eq!(emitter.listener_count::<Message>(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "different type of data than registered")]
+    fn test_trigger_with_other_data_type_panics() {
+        let emitter = EventEmitter::new();
+        emitter.on::<Both, _, u32>(|_| {});
+        emitter.trigger::<Both, String>(&"not a u32".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "more than one type of data")]
+    fn test_register_with_other_data_type_panics() {
+        let emitter = EventEmitter::new();
+        emitter.on::<Both, _, u32>(|_| {});
+        emitter.on::<Both, _, String>(|_| {});
+    }
+}
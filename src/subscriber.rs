@@ -0,0 +1,242 @@
+//! A builder for callbacks which filter, convert or limit the events they see.
+
+use std::any::TypeId;
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::{continuing, Callback, Event, EventEmitter, ListenerId, Registration, Subscription};
+
+// Passes the data of an event, as converted by the stages so far, on to the
+// next stage, or drops the event.
+type Stages<X, Y> = Box<dyn Fn(&X, &mut dyn FnMut(&Y))>;
+
+/// A callback for the event `E` being built up in stages, see
+/// `Eventable::subscribe`.
+///
+/// Each stage sees the events passed on by the stages before it, so
+/// `filter(..).take(3)` sees the first three events matching the filter,
+/// while `take(3).filter(..)` sees those of the first three events which do.
+#[must_use = "no callback is registered until handle is called"]
+pub struct Subscriber<'a, E, X: ?Sized + 'static, Y: ?Sized + 'static> {
+    emitter: &'a EventEmitter,
+    stages: Stages<X, Y>,
+    // Set once a `take` stage has passed on its last event.
+    done: Rc<Cell<bool>>,
+    event: PhantomData<fn() -> E>,
+}
+
+impl<'a, E, X> Subscriber<'a, E, X, X>
+where E: Event<X>, X: ?Sized + 'static {
+    pub(crate) fn new(emitter: &'a EventEmitter) -> Subscriber<'a, E, X, X> {
+        Subscriber {
+            emitter,
+            stages: Box::new(|event, next| next(event)),
+            done: Rc::new(Cell::new(false)),
+            event: PhantomData,
+        }
+    }
+}
+
+impl<'a, E, X, Y> Subscriber<'a, E, X, Y>
+where E: Event<X>, X: ?Sized + 'static, Y: ?Sized + 'static {
+    /// Only pass on events for which `predicate` returns true.
+    pub fn filter<P>(self, predicate: P) -> Subscriber<'a, E, X, Y>
+    where P: Fn(&Y) -> bool + 'static {
+        let stages = self.stages;
+        Self::then(self.emitter, self.done, move |event, next| {
+            stages(event, &mut |data| if predicate(data) { next(data) })
+        })
+    }
+
+    /// Convert the data of each event with `map`.
+    pub fn map<G, Z>(self, map: G) -> Subscriber<'a, E, X, Z>
+    where G: Fn(&Y) -> Z + 'static, Z: 'static {
+        let stages = self.stages;
+        Self::then(self.emitter, self.done, move |event, next| stages(event, &mut |data| next(&map(data))))
+    }
+
+    /// Only pass on the first `n` events, removing the callback as soon as
+    /// the last of them has been handled.
+    ///
+    /// With `take(0)`, `handle` registers nothing at all.
+    pub fn take(self, n: usize) -> Subscriber<'a, E, X, Y> {
+        let (stages, done) = (self.stages, self.done.clone());
+        let remaining = Cell::new(n);
+        if n == 0 { done.set(true) }
+
+        Self::then(self.emitter, self.done, move |event, next| stages(event, &mut |data| {
+            if remaining.get() == 0 { return }
+
+            remaining.set(remaining.get() - 1);
+            if remaining.get() == 0 { done.set(true) }
+            next(data)
+        }))
+    }
+
+    /// Drop the first `n` events, passing on the rest.
+    pub fn skip(self, n: usize) -> Subscriber<'a, E, X, Y> {
+        let stages = self.stages;
+        let skipped = Cell::new(0);
+
+        Self::then(self.emitter, self.done, move |event, next| stages(event, &mut |data| {
+            if skipped.get() < n { skipped.set(skipped.get() + 1) } else { next(data) }
+        }))
+    }
+
+    /// Drop events which follow the previous event, passed on or not, within
+    /// `period`.
+    ///
+    /// The emitter has no timer to deliver an event once things have gone
+    /// quiet, so this passes on the first event of each burst rather than
+    /// the last.
+    pub fn debounce(self, period: Duration) -> Subscriber<'a, E, X, Y> {
+        let stages = self.stages;
+        let last = Cell::new(None::<Instant>);
+
+        Self::then(self.emitter, self.done, move |event, next| stages(event, &mut |data| {
+            let now = Instant::now();
+            let quiet = last.get().is_none_or(|last| now.duration_since(last) >= period);
+
+            last.set(Some(now));
+            if quiet { next(data) }
+        }))
+    }
+
+    /// Register `callback` to be fired with the events passed on by every
+    /// stage.
+    ///
+    /// The returned `ListenerId` can be passed to `off` to remove the callback.
+    pub fn handle<F>(self, callback: F) -> ListenerId
+    where F: Fn(&Y) + 'static {
+        let emitter = self.emitter;
+        if self.done.get() { return emitter.next_id() }
+
+        // Removes the listener once the last event allowed by `take` is handled.
+        let expiry: Rc<RefCell<Option<Subscription>>> = Rc::new(RefCell::new(None));
+
+        let (stages, done, inner) = (self.stages, self.done, expiry.clone());
+        let callback = continuing(move |event: &X| {
+            stages(event, &mut |data| callback(data));
+            if done.get() { drop(inner.borrow_mut().take()) }
+        });
+
+        let id = emitter.register::<E, X>(Registration::default(), Callback::Ref(Rc::new(callback)));
+        let table = Rc::downgrade(&emitter.events);
+        *expiry.borrow_mut() = Some(Subscription { table, event: TypeId::of::<E>(), id });
+
+        id
+    }
+
+    fn then<Z, S>(emitter: &'a EventEmitter, done: Rc<Cell<bool>>, stages: S) -> Subscriber<'a, E, X, Z>
+    where Z: ?Sized + 'static, S: Fn(&X, &mut dyn FnMut(&Z)) + 'static {
+        Subscriber { emitter, stages: Box::new(stages), done, event: PhantomData }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::{Event, EventEmitter, Eventable};
+
+    struct Key;
+    impl Event<(char, bool)> for Key {}
+
+    #[test]
+    fn test_filter_map_skip_and_take() {
+        let emitter = EventEmitter::new();
+        let seen = Rc::new(RefCell::new(vec![]));
+
+        let inner = seen.clone();
+        emitter.subscribe::<Key, (char, bool)>()
+            .filter(|&(_, ctrl)| ctrl)
+            .map(|&(key, _)| key.to_ascii_uppercase())
+            .skip(1)
+            .take(2)
+            .handle(move |key| inner.borrow_mut().push(*key));
+
+        for key in [('a', true), ('b', false), ('c', true), ('d', true), ('e', true)] {
+            emitter.trigger::<Key, (char, bool)>(&key);
+        }
+
+        assert_eq!(*seen.borrow(), vec!['C', 'D']);
+        assert!(!emitter.has_listeners::<Key>());
+    }
+
+    #[test]
+    fn test_handle_without_stages() {
+        let emitter = EventEmitter::new();
+        let seen = Rc::new(RefCell::new(vec![]));
+
+        let inner = seen.clone();
+        let id = emitter.subscribe::<Key, (char, bool)>()
+            .handle(move |&(key, _)| inner.borrow_mut().push(key));
+
+        emitter.trigger::<Key, (char, bool)>(&('a', false));
+        emitter.trigger::<Key, (char, bool)>(&('b', true));
+
+        assert_eq!(*seen.borrow(), vec!['a', 'b']);
+        assert!(emitter.off::<Key, (char, bool)>(id));
+    }
+
+    #[test]
+    fn test_take_removes_the_listener() {
+        let emitter = EventEmitter::new();
+
+        let id = emitter.subscribe::<Key, (char, bool)>().take(0).handle(|_| panic!("taken"));
+        assert!(!emitter.has_listeners::<Key>());
+        emitter.trigger::<Key, (char, bool)>(&('a', false));
+        assert!(!emitter.off::<Key, (char, bool)>(id));
+
+        emitter.subscribe::<Key, (char, bool)>().take(2).handle(|_| {});
+        emitter.trigger::<Key, (char, bool)>(&('a', false));
+        assert!(emitter.has_listeners::<Key>());
+        emitter.trigger::<Key, (char, bool)>(&('b', false));
+        assert!(!emitter.has_listeners::<Key>());
+    }
+
+    #[test]
+    fn test_debounce() {
+        let emitter = EventEmitter::new();
+        let seen = Rc::new(RefCell::new(vec![]));
+
+        let inner = seen.clone();
+        emitter.subscribe::<Key, (char, bool)>()
+            .debounce(Duration::from_secs(3600))
+            .handle(move |&(key, _)| inner.borrow_mut().push(key));
+        let inner = seen.clone();
+        emitter.subscribe::<Key, (char, bool)>()
+            .debounce(Duration::from_millis(10))
+            .handle(move |&(key, _)| inner.borrow_mut().push(key.to_ascii_uppercase()));
+
+        emitter.trigger::<Key, (char, bool)>(&('a', false));
+        thread::sleep(Duration::from_millis(20));
+        emitter.trigger::<Key, (char, bool)>(&('b', false));
+
+        assert_eq!(*seen.borrow(), vec!['a', 'A', 'B']);
+    }
+
+    #[test]
+    fn test_take_counts_events_passed_on() {
+        let emitter = EventEmitter::new();
+        let seen = Rc::new(RefCell::new(vec![]));
+
+        let inner = seen.clone();
+        let id = emitter.subscribe::<Key, (char, bool)>()
+            .take(2)
+            .filter(|&(_, ctrl)| ctrl)
+            .handle(move |&(key, _)| inner.borrow_mut().push(key));
+
+        emitter.trigger::<Key, (char, bool)>(&('a', false));
+        emitter.trigger::<Key, (char, bool)>(&('b', true));
+        emitter.trigger::<Key, (char, bool)>(&('c', true));
+
+        assert_eq!(*seen.borrow(), vec!['b']);
+        assert!(!emitter.off::<Key, (char, bool)>(id));
+    }
+}
@@ -0,0 +1,337 @@
+//! A thread-safe event emitter.
+
+use std::any::{Any, TypeId};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, Weak};
+
+use crate::{continuing, Event, ListenerId, Propagation, Registration};
+
+type Owner = Weak<dyn Any + Send + Sync>;
+
+// The thread-safe counterparts of `Listener`, `Handlers` and `HandlerList` in
+// the crate root, which work in the same way.
+struct Listener<X: ?Sized> {
+    id: ListenerId,
+    once: Option<Arc<AtomicBool>>,
+    owner: Option<Owner>,
+    priority: i32,
+    callback: Arc<dyn Fn(&X) -> Propagation + Send + Sync>,
+}
+
+impl<X: ?Sized> Clone for Listener<X> {
+    fn clone(&self) -> Listener<X> {
+        Listener {
+            id: self.id,
+            once: self.once.clone(),
+            owner: self.owner.clone(),
+            priority: self.priority,
+            callback: self.callback.clone(),
+        }
+    }
+}
+
+struct Handlers<X: ?Sized + 'static> {
+    list: Arc<Vec<Listener<X>>>,
+}
+
+trait HandlerList: Send + Sync {
+    fn remove(&mut self, ids: &[ListenerId]) -> Option<Box<dyn Any + Send + Sync>>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<X: ?Sized + 'static> HandlerList for Handlers<X> {
+    fn remove(&mut self, ids: &[ListenerId]) -> Option<Box<dyn Any + Send + Sync>> {
+        if !self.list.iter().any(|listener| ids.contains(&listener.id)) { return None }
+
+        let list = self.list.iter().filter(|listener| !ids.contains(&listener.id)).cloned().collect();
+        Some(Box::new(mem::replace(&mut self.list, Arc::new(list))))
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+struct Table {
+    events: HashMap<TypeId, Box<dyn HandlerList>>,
+    next_id: usize,
+}
+
+/// An event emitter which can be shared between threads.
+///
+/// Unlike with `EventEmitter`, callbacks must be `Send + Sync`.
+///
+/// A trigger dispatches to a snapshot of the callbacks registered when it
+/// started, and holds no lock while calling them. Callbacks may therefore
+/// freely call `on`, `off` and `trigger` on the same emitter; changes they
+/// make apply from the next trigger onwards.
+pub struct SyncEventEmitter {
+    table: RwLock<Table>,
+}
+
+impl SyncEventEmitter {
+    /// Create a SyncEventEmitter with no registered handlers.
+    pub fn new() -> SyncEventEmitter {
+        SyncEventEmitter { table: RwLock::new(Table { events: HashMap::new(), next_id: 0 }) }
+    }
+
+    fn register<E, F, X>(&self, registration: Registration<Owner>, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) -> Propagation + Send + Sync + 'static, X: ?Sized + 'static {
+        let Registration { once, priority, owner } = registration;
+        let mut table = self.table.write().unwrap_or_else(PoisonError::into_inner);
+        let table = &mut *table;
+
+        let id = ListenerId(table.next_id);
+        table.next_id += 1;
+
+        let handlers = match table.events.entry(TypeId::of::<E>()) {
+            Entry::Occupied(occupied) => occupied.into_mut(),
+            Entry::Vacant(vacant) => vacant.insert(Box::new(Handlers::<X> { list: Arc::new(vec![]) }))
+        };
+
+        let handlers = match handlers.as_any_mut().downcast_mut::<Handlers<X>>() {
+            Some(handlers) => handlers,
+            None => panic!("event registered with more than one type of data")
+        };
+
+        let list = Arc::make_mut(&mut handlers.list);
+        let index = list.iter()
+            .position(|listener| listener.priority < priority)
+            .unwrap_or(list.len());
+        list.insert(index, Listener {
+            id,
+            once: if once { Some(Arc::new(AtomicBool::new(false))) } else { None },
+            owner,
+            priority,
+            callback: Arc::new(callback),
+        });
+
+        id
+    }
+
+    fn remove<E: 'static>(&self, ids: &[ListenerId]) -> bool {
+        let mut table = self.table.write().unwrap_or_else(PoisonError::into_inner);
+        let removed = table.events.get_mut(&TypeId::of::<E>()).and_then(|handlers| handlers.remove(ids));
+
+        // As in `EventEmitter`, dropping a callback may call back into the
+        // emitter, so the lock is released first.
+        drop(table);
+        removed.is_some()
+    }
+}
+
+impl Default for SyncEventEmitter {
+    fn default() -> SyncEventEmitter { SyncEventEmitter::new() }
+}
+
+/// The thread-safe counterpart of `Eventable`.
+///
+/// A type is SyncEventable if it contains a SyncEventEmitter.
+pub trait SyncEventable {
+    /// Get a reference to the enclosed emitter.
+    fn events(&self) -> &SyncEventEmitter;
+
+    /// Register a callback to be fired when an event is triggered.
+    ///
+    /// Many callbacks can be registered for a single event. The returned
+    /// `ListenerId` can be passed to `off` to remove the callback.
+    fn on<E, F, X>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) + Send + Sync + 'static, X: ?Sized + 'static {
+        self.events().register::<E, _, X>(Registration::default(), continuing(callback))
+    }
+
+    /// Register a callback which is only kept while `owner` is alive, see
+    /// `Eventable::on_weak`.
+    fn on_weak<E, F, X, T>(&self, owner: &Arc<T>, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&Arc<T>, &X) + Send + Sync + 'static, X: ?Sized + 'static,
+          T: Send + Sync + 'static {
+        let weak = Arc::downgrade(owner);
+        let owner = Some(weak.clone() as Owner);
+        let callback = continuing(move |event: &X| {
+            if let Some(owner) = weak.upgrade() { callback(&owner, event) }
+        });
+
+        self.events().register::<E, _, X>(Registration { owner, ..Registration::default() }, callback)
+    }
+
+    /// Register a callback which decides whether the event propagates any
+    /// further.
+    fn on_propagating<E, F, X>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) -> Propagation + Send + Sync + 'static, X: ?Sized + 'static {
+        self.events().register::<E, F, X>(Registration::default(), callback)
+    }
+
+    /// Register a callback with a priority, see `Eventable::on_with_priority`.
+    fn on_with_priority<E, F, X>(&self, priority: i32, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) + Send + Sync + 'static, X: ?Sized + 'static {
+        let registration = Registration { priority, ..Registration::default() };
+        self.events().register::<E, _, X>(registration, continuing(callback))
+    }
+
+    /// Register a callback to be fired only the next time an event is triggered.
+    ///
+    /// If several threads trigger the event at once, exactly one of them
+    /// calls the callback.
+    fn once<E, F, X>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&X) + Send + Sync + 'static, X: ?Sized + 'static {
+        let registration = Registration { once: true, ..Registration::default() };
+        self.events().register::<E, _, X>(registration, continuing(callback))
+    }
+
+    /// Remove a callback previously registered for this event with `on`.
+    ///
+    /// Returns false if no such callback was registered.
+    fn off<E, X>(&self, id: ListenerId) -> bool
+    where E: Event<X>, X: ?Sized + 'static {
+        self.events().remove::<E>(&[id])
+    }
+
+    /// Trigger an event, calling all of the associated handlers.
+    ///
+    /// Returns true if a handler stopped propagation of the event.
+    fn trigger<E, X>(&self, event: &X) -> bool
+    where E: Event<X>, X: ?Sized + 'static {
+        let emitter = self.events();
+
+        let list = {
+            let table = emitter.table.read().unwrap_or_else(PoisonError::into_inner);
+
+            match table.events.get(&TypeId::of::<E>()) {
+                Some(handlers) => match handlers.as_any().downcast_ref::<Handlers<X>>() {
+                    Some(handlers) => handlers.list.clone(),
+                    None => panic!("event triggered with a different type of data than registered")
+                },
+                None => return false
+            }
+        };
+
+        let mut spent = vec![];
+        let mut stopped = false;
+
+        for listener in list.iter() {
+            if let Some(ref owner) = listener.owner {
+                if owner.strong_count() == 0 {
+                    spent.push(listener.id);
+                    continue;
+                }
+            }
+
+            if let Some(ref fired) = listener.once {
+                if fired.swap(true, Ordering::SeqCst) { continue }
+                spent.push(listener.id);
+            }
+
+            if (listener.callback)(event) == Propagation::Stop {
+                stopped = true;
+                break;
+            }
+        }
+
+        if !spent.is_empty() { emitter.remove::<E>(&spent); }
+
+        stopped
+    }
+}
+
+// SyncEventEmitter is itself eventable, so can be used directly.
+impl SyncEventable for SyncEventEmitter {
+    fn events(&self) -> &SyncEventEmitter { self }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use super::{SyncEventEmitter, SyncEventable};
+    use crate::{Event, Propagation};
+
+    struct Tick;
+    impl Event<usize> for Tick {}
+
+    #[test]
+    fn test_trigger_from_many_threads() {
+        let emitter = Arc::new(SyncEventEmitter::new());
+        let total = Arc::new(AtomicUsize::new(0));
+
+        let inner = total.clone();
+        emitter.on::<Tick, _, usize>(move |x| { inner.fetch_add(*x, Ordering::SeqCst); });
+
+        let threads: Vec<_> = (0..8).map(|_| {
+            let emitter = emitter.clone();
+            thread::spawn(move || emitter.trigger::<Tick, usize>(&1))
+        }).collect();
+
+        for thread in threads { thread.join().unwrap(); }
+        assert_eq!(total.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn test_once_fires_a_single_time_across_threads() {
+        let emitter = Arc::new(SyncEventEmitter::new());
+        let total = Arc::new(AtomicUsize::new(0));
+
+        let inner = total.clone();
+        let id = emitter.once::<Tick, _, usize>(move |_| { inner.fetch_add(1, Ordering::SeqCst); });
+
+        let threads: Vec<_> = (0..8).map(|_| {
+            let emitter = emitter.clone();
+            thread::spawn(move || emitter.trigger::<Tick, usize>(&1))
+        }).collect();
+
+        for thread in threads { thread.join().unwrap(); }
+        assert_eq!(total.load(Ordering::SeqCst), 1);
+        assert!(!emitter.off::<Tick, usize>(id));
+    }
+
+    #[test]
+    fn test_handlers_can_register_during_trigger() {
+        let emitter = Arc::new(SyncEventEmitter::new());
+        let seen = Arc::new(Mutex::new(vec![]));
+
+        let (inner, seen_inner) = (emitter.clone(), seen.clone());
+        emitter.once::<Tick, _, usize>(move |_| {
+            let seen = seen_inner.clone();
+            inner.on::<Tick, _, usize>(move |x| seen.lock().unwrap().push(*x));
+        });
+
+        // The new handler only sees triggers after the one that added it.
+        emitter.trigger::<Tick, usize>(&1);
+        emitter.trigger::<Tick, usize>(&2);
+        assert_eq!(*seen.lock().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn test_weak_listener_is_pruned_with_its_owner() {
+        let emitter = SyncEventEmitter::new();
+        let owner = Arc::new(AtomicUsize::new(0));
+
+        let id = emitter.on_weak::<Tick, _, usize, _>(&owner, |owner, x| {
+            owner.fetch_add(*x, Ordering::SeqCst);
+        });
+
+        emitter.trigger::<Tick, usize>(&2);
+        assert_eq!(owner.load(Ordering::SeqCst), 2);
+
+        drop(owner);
+        emitter.trigger::<Tick, usize>(&2);
+        assert!(!emitter.off::<Tick, usize>(id));
+    }
+
+    #[test]
+    fn test_stop_propagation() {
+        let emitter = SyncEventEmitter::new();
+        let total = Arc::new(AtomicUsize::new(0));
+
+        emitter.on_propagating::<Tick, _, usize>(|_| Propagation::Stop);
+        let inner = total.clone();
+        emitter.on::<Tick, _, usize>(move |_| { inner.fetch_add(1, Ordering::SeqCst); });
+
+        assert!(emitter.trigger::<Tick, usize>(&1));
+        assert_eq!(total.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,239 @@
+//! Event bubbling through a tree of `Eventable` nodes.
+
+use std::fmt;
+use std::rc::Rc;
+
+use crate::{snapshot, Event, Eventable, ListenerId, Propagation, Registration};
+
+/// The phase of a bubbling event in which a callback is called.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Phase {
+    /// The event is travelling from the root down to the target's parent.
+    Capture,
+    /// The event has reached its target.
+    Target,
+    /// The event is travelling from the target's parent up to the root.
+    Bubble,
+}
+
+/// A bubbling event, as seen by the callbacks of one node.
+pub struct Bubbling<'a, N, X: ?Sized> {
+    data: &'a X,
+    target: &'a N,
+    current: &'a N,
+    phase: Phase,
+}
+
+impl<'a, N, X: ?Sized> Bubbling<'a, N, X> {
+    /// The data the event was triggered with.
+    pub fn data(&self) -> &'a X { self.data }
+
+    /// The node the event was triggered on.
+    pub fn target(&self) -> &'a N { self.target }
+
+    /// The node whose callbacks are being called.
+    pub fn current(&self) -> &'a N { self.current }
+
+    /// The phase the event is in.
+    pub fn phase(&self) -> Phase { self.phase }
+}
+
+impl<'a, N, X: ?Sized> fmt::Debug for Bubbling<'a, N, X> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bubbling").field("phase", &self.phase).finish_non_exhaustive()
+    }
+}
+
+// A callback registered with `on_capture` or `on_bubble`, with its phase.
+type PhaseCallback<N, X> = (Phase, Rc<dyn Fn(&Bubbling<'_, N, X>) -> Propagation>);
+
+/// An `Eventable` which is part of a tree of nodes of the same type.
+///
+/// Events triggered with `trigger_bubbling` travel through the ancestors of
+/// the node, like events in the DOM: the callbacks registered with
+/// `on_capture` are called from the root down to the node's parent, then
+/// all callbacks on the node itself, then those registered with `on_bubble`
+/// from the parent back up to the root.
+pub trait Node: Eventable + Sized + 'static {
+    /// Get the parent of this node, or None if it is a root.
+    fn parent(&self) -> Option<Rc<Self>>;
+
+    /// Register a callback to be fired when an event travels down through
+    /// this node, or reaches it.
+    ///
+    /// When the callback returns `Propagation::Stop`, all remaining callbacks
+    /// on this node and the rest of the path are skipped. The returned
+    /// `ListenerId` can be passed to `off`.
+    fn on_capture<E, F, X>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&Bubbling<'_, Self, X>) -> Propagation + 'static,
+          X: ?Sized + 'static {
+        let emitter = self.events();
+        let callback: PhaseCallback<Self, X> = (Phase::Capture, Rc::new(callback));
+        emitter.insert::<E, _>(&emitter.phases, Registration::default(), callback)
+    }
+
+    /// Register a callback to be fired when an event reaches this node, or
+    /// travels up through it.
+    ///
+    /// Stopping propagation works as for `on_capture`.
+    fn on_bubble<E, F, X>(&self, callback: F) -> ListenerId
+    where E: Event<X>, F: Fn(&Bubbling<'_, Self, X>) -> Propagation + 'static,
+          X: ?Sized + 'static {
+        let emitter = self.events();
+        let callback: PhaseCallback<Self, X> = (Phase::Bubble, Rc::new(callback));
+        emitter.insert::<E, _>(&emitter.phases, Registration::default(), callback)
+    }
+
+    /// Trigger an event on this node, sending it through its ancestors.
+    ///
+    /// Callbacks registered with `on` and the like are not called, except
+    /// for those registered with `on_any` on this node.
+    ///
+    /// Returns true if a callback stopped propagation of the event.
+    fn trigger_bubbling<E, X>(&self, event: &X) -> bool
+    where E: Event<X>, X: ?Sized + 'static {
+        self.events().notify_any::<E, X>(event);
+
+        let mut ancestors = vec![];
+        let mut next = self.parent();
+        while let Some(node) = next {
+            next = node.parent();
+            ancestors.push(node);
+        }
+
+        let visit = |current: &Self, phase| {
+            visit::<Self, E, X>(&Bubbling { data: event, target: self, current, phase })
+        };
+
+        ancestors.iter().rev().any(|node| visit(node, Phase::Capture))
+            || visit(self, Phase::Target)
+            || ancestors.iter().any(|node| visit(node, Phase::Bubble))
+    }
+}
+
+// Call the callbacks of the current node for the phase of the event,
+// returning true if one of them stopped propagation.
+fn visit<N, E, X>(event: &Bubbling<'_, N, X>) -> bool
+where N: Node, E: Event<X>, X: ?Sized + 'static {
+    let emitter = event.current.events();
+
+    let list = match snapshot::<E, PhaseCallback<N, X>>(&emitter.phases) {
+        Some(list) => list,
+        None => return false
+    };
+
+    let mut spent = vec![];
+    let mut stopped = false;
+
+    for listener in list.iter() {
+        let (phase, ref callback) = listener.callback;
+        if event.phase != Phase::Target && event.phase != phase { continue }
+
+        if !listener.claim(&mut spent) { continue }
+
+        if emitter.call(listener.id, || callback(event)) == Some(Propagation::Stop) {
+            stopped = true;
+            break;
+        }
+    }
+
+    if !spent.is_empty() { emitter.remove::<E>(&spent); }
+
+    stopped
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{Node, Phase};
+    use crate::{Event, EventEmitter, Eventable, Propagation};
+
+    struct Click;
+    impl Event<u32> for Click {}
+
+    struct Widget {
+        name: &'static str,
+        parent: Option<Rc<Widget>>,
+        events: EventEmitter,
+    }
+
+    impl Eventable for Widget {
+        fn events(&self) -> &EventEmitter { &self.events }
+        fn events_mut(&mut self) -> &mut EventEmitter { &mut self.events }
+    }
+
+    impl Node for Widget {
+        fn parent(&self) -> Option<Rc<Widget>> { self.parent.clone() }
+    }
+
+    fn widget(name: &'static str, parent: Option<&Rc<Widget>>) -> Rc<Widget> {
+        Rc::new(Widget { name, parent: parent.cloned(), events: EventEmitter::new() })
+    }
+
+    // Record every phase on every widget as "phase:current:target".
+    fn record(widgets: &[&Rc<Widget>], seen: &Rc<RefCell<Vec<String>>>) {
+        for widget in widgets {
+            let inner = seen.clone();
+            widget.on_capture::<Click, _, u32>(move |event| {
+                let entry = format!("{:?}:{}:{}", event.phase(), event.current().name, event.target().name);
+                inner.borrow_mut().push(entry);
+                Propagation::Continue
+            });
+            let inner = seen.clone();
+            widget.on_bubble::<Click, _, u32>(move |event| {
+                let entry = format!("{:?}:{}:{}", event.phase(), event.current().name, event.target().name);
+                inner.borrow_mut().push(entry);
+                Propagation::Continue
+            });
+        }
+    }
+
+    #[test]
+    fn test_capture_target_and_bubble() {
+        let root = widget("root", None);
+        let panel = widget("panel", Some(&root));
+        let button = widget("button", Some(&panel));
+        let seen = Rc::new(RefCell::new(vec![]));
+        record(&[&root, &panel, &button], &seen);
+
+        assert!(!button.trigger_bubbling::<Click, u32>(&1));
+        assert_eq!(*seen.borrow(), vec![
+            "Capture:root:button",
+            "Capture:panel:button",
+            "Target:button:button",
+            "Target:button:button",
+            "Bubble:panel:button",
+            "Bubble:root:button",
+        ]);
+    }
+
+    #[test]
+    fn test_stop_propagation() {
+        let root = widget("root", None);
+        let panel = widget("panel", Some(&root));
+        let button = widget("button", Some(&panel));
+        let seen = Rc::new(RefCell::new(vec![]));
+
+        panel.on_bubble::<Click, _, u32>(|event| {
+            if *event.data() > 5 { Propagation::Stop } else { Propagation::Continue }
+        });
+        record(&[&root, &panel, &button], &seen);
+
+        assert!(button.trigger_bubbling::<Click, u32>(&7));
+        assert_eq!(seen.borrow().last().map(String::as_str), Some("Target:button:button"));
+
+        seen.borrow_mut().clear();
+        let id = root.on_capture::<Click, _, u32>(|event| {
+            assert_eq!(event.phase(), Phase::Capture);
+            Propagation::Stop
+        });
+        assert!(button.trigger_bubbling::<Click, u32>(&1));
+        assert_eq!(*seen.borrow(), vec!["Capture:root:button"]);
+
+        assert!(root.off::<Click, u32>(id));
+        assert!(!button.trigger_bubbling::<Click, u32>(&1));
+        assert_eq!(seen.borrow().len(), 7);
+    }
+}